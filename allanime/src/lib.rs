@@ -1,8 +1,12 @@
-use eyre::OptionExt;
-use serde::{Deserialize, Serialize};
-use tokio::sync::OnceCell;
+use std::{borrow::Cow, time::Duration};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use eyre::{Context, OptionExt, bail, eyre};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+pub mod resolve;
+pub use resolve::{StreamKind, StreamLink, Subtitle};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum AllanimeHosterName {
     Default,
     Ok,
@@ -41,169 +45,514 @@ pub enum AllanimeType {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SourceUrl {
-    source_url: Box<str>,
-    source_name: AllanimeHosterName,
+    /// Possibly obfuscated, see [`SourceUrl::url`]
+    pub source_url: Box<str>,
+    pub source_name: AllanimeHosterName,
     #[serde(rename = "type")]
-    source_type: Option<AllanimeType>,
-    priority: f64,
-}
-static IFRAME_HEAD: OnceCell<String> = OnceCell::const_new();
-
-pub async fn query_allanime(query: String, episode: u32) -> eyre::Result<Vec<String>> {
-    let c = reqwest::Client::new();
-    let body = serde_json::json!({
-    "variables": {
-      "search": {
-        "query": query,
-        "allowAdult": false,
-        "allowUnknown": false,
-      },
-      "limit": 26,
-      "page": 1,
-      "translationType": "sub",
-      "countryOrigin": "ALL",
-    },
-    "query":
-        "query(
-            $search:SearchInput
-            $limit:Int
-            $page:Int
-            $translationType:VaildTranslationTypeEnumType
-            $countryOrigin:VaildCountryOriginEnumType
-        ) {
-            shows(
-                search: $search
-                limit: $limit
-                page: $page
-                translationType: $translationType
-                countryOrigin: $countryOrigin
-            ) {
-                pageInfo {
-                    total
-                }
-                edges {
-                    _id
-                    name
-                    thumbnail
-                    englishName
-                    nativeName
-                    slugTime
-                }
-            }
-        }",
-      });
-    let br = c
-        .post("https://api.allanime.day/api")
-        .header("Content-Type", "application/json; charset=utf-8")
-        .header("Referer", "https://api.allanime.day/")
-        .body(body.to_string())
-        .send()
-        .await?
-        .bytes()
-        .await?;
-    let v = serde_json::from_slice::<serde_json::Value>(&br)?;
-    println!("{v:#?}");
-    let showid = v["data"]["shows"]["edges"]
-        .as_array()
-        .unwrap()
-        .first()
-        .unwrap()["_id"]
-        .as_str()
-        .unwrap();
-    let body = serde_json::json!(
-        {
-          "variables": {
-            "showId": showid,
-            "translationType": "sub",
-            "episodeString": episode.to_string(),
-          },
-          "query":
-              "query(
-                $showId:String!,
-                $translationType: VaildTranslationTypeEnumType!,
-                $episodeString: String!
-              ) {
-                episode(
-                showId: $showId
-                translationType: $translationType
-                episodeString: $episodeString
-                ) {
-                    sourceUrls
-                }
-              }",
-        }
-    );
-    let br = c
-        .post("https://api.allanime.day/api")
-        .header("Content-Type", "application/json; charset=utf-8")
-        .header("Referer", "https://api.allanime.day/")
-        .body(body.to_string())
-        .send()
-        .await?
-        .bytes()
-        .await?;
-    let v = serde_json::from_slice::<serde_json::Value>(&br)?;
-
-    for source in v["data"]["episode"]["sourceUrls"].as_array().unwrap() {
-        let url: SourceUrl = serde_json::from_value(source.clone())?;
-        let decrypted = quote_unquote_decrypt(&url.source_url);
-        let real_url: &str = decrypted.as_deref().unwrap_or(&url.source_url);
-        match &url.source_name {
-            _ if real_url.starts_with("/apivtwo") => {
-                // handle internal source
-            }
-            _ if url.source_type == Some(AllanimeType::Player) => {
-                let head = IFRAME_HEAD
-                    .get_or_try_init::<eyre::Report, _, _>(|| async move {
-                        let resp = reqwest::get("https://allanime.ai/getVersion").await?;
-                        let b = resp.bytes().await?;
-                        dbg!(str::from_utf8(&b));
-                        // .json::<serde_json::Value>()
-                        // .await?
-                        // .get("episodeIframeHead")
-                        // .ok_or_eyre("allanime iframe head not present")
-                        // .map(ToString::to_string)
-                        todo!()
-                    })
-                    .await?;
-                dbg!(head);
-            }
-            AllanimeHosterName::Other(_) => {}
-            _ => {}
-        }
-
-        dbg!(real_url, &url.source_name, &url.source_type);
-        println!();
-    }
-
-    println!("{v:#?}");
-
-    todo!()
+    pub source_type: Option<AllanimeType>,
+    pub priority: f64,
 }
 
+impl SourceUrl {
+    /// The source URL with the obfuscation removed. Either absolute, or a path on allanime's own servers (e.g. `/apivtwo/...`)
+    pub fn url(&self) -> Cow<'_, str> {
+        match quote_unquote_decrypt(&self.source_url) {
+            Some(v) => Cow::Owned(v),
+            None => Cow::Borrowed(&self.source_url),
+        }
+    }
+}
+
+/// Undoes the hex + xor obfuscation allanime applies to most source URLs (those prefixed with `-`).
+/// Returns `None` if `s` is not obfuscated this way.
 pub fn quote_unquote_decrypt(s: &str) -> Option<String> {
     if s.starts_with('-')
         && let Some(s) = s.split('-').next_back()
         && s.is_ascii()
         && s.len() % 2 == 0
     {
-        Some(
-            s.as_bytes()
-                .chunks(2)
-                .map(|v| (u8::from_str_radix(str::from_utf8(v).unwrap(), 16).unwrap() ^ 56) as char)
-                .collect(),
-        )
+        s.as_bytes()
+            .chunks(2)
+            .map(|v| {
+                let byte = u8::from_str_radix(str::from_utf8(v).ok()?, 16).ok()?;
+                Some((byte ^ 56) as char)
+            })
+            .collect()
     } else {
         None
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranslationType {
+    #[default]
+    Sub,
+    Dub,
+    Raw,
+}
+
+impl TranslationType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TranslationType::Sub => "sub",
+            TranslationType::Dub => "dub",
+            TranslationType::Raw => "raw",
+        }
+    }
+}
+
+/// A search result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllAnimeShow {
+    #[serde(rename = "_id")]
+    pub id: Box<str>,
+    /// Usually the romaji name
+    pub name: Box<str>,
+    pub english_name: Option<Box<str>>,
+    pub native_name: Option<Box<str>>,
+    #[serde(default)]
+    pub alt_names: Vec<Box<str>>,
+    pub thumbnail: Option<Box<str>>,
+    /// Total number of episodes of the show, if known. Not necessarily all available yet
+    #[serde(default, deserialize_with = "de_episode_count")]
+    pub episode_count: Option<u32>,
+    #[serde(default)]
+    pub available_episodes: AvailableEpisodes,
+}
+
+impl AllAnimeShow {
+    /// Every known name of the show
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        [
+            Some(&self.name),
+            self.english_name.as_ref(),
+            self.native_name.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(&self.alt_names)
+        .map(|v| &**v)
+    }
+}
+
+/// allanime sends the episode count as a string, or not at all
+fn de_episode_count<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Count {
+        Num(u32),
+        Str(String),
+    }
+    Ok(match Option::<Count>::deserialize(d)? {
+        Some(Count::Num(v)) => Some(v),
+        Some(Count::Str(s)) => s.parse().ok(),
+        None => None,
+    })
+}
+
+/// Number of episodes released per translation
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AvailableEpisodes {
+    pub sub: u32,
+    pub dub: u32,
+    pub raw: u32,
+}
+
+impl AvailableEpisodes {
+    pub fn get(&self, translation: TranslationType) -> u32 {
+        match translation {
+            TranslationType::Sub => self.sub,
+            TranslationType::Dub => self.dub,
+            TranslationType::Raw => self.raw,
+        }
+    }
+}
+
+/// Episode strings (e.g. `"1"`, `"6.5"`) available per translation, in ascending order
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EpisodeList {
+    pub sub: Vec<Box<str>>,
+    pub dub: Vec<Box<str>>,
+    pub raw: Vec<Box<str>>,
+}
+
+impl EpisodeList {
+    pub fn get(&self, translation: TranslationType) -> &[Box<str>] {
+        match translation {
+            TranslationType::Sub => &self.sub,
+            TranslationType::Dub => &self.dub,
+            TranslationType::Raw => &self.raw,
+        }
+    }
+    fn sort(&mut self) {
+        // numeric order, with anything that is not a number (e.g. "OVA") at the end
+        fn key(v: &str) -> f64 {
+            v.parse().unwrap_or(f64::INFINITY)
+        }
+        for list in [&mut self.sub, &mut self.dub, &mut self.raw] {
+            list.sort_by(|a, b| key(a).total_cmp(&key(b)).then_with(|| a.cmp(b)));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AllAnimeClientConfig {
+    /// URL of the GraphQL endpoint, *including the protocol*
+    pub api_url: Box<str>,
+    /// Site that `/apivtwo/...` source links are relative to
+    pub site_url: Box<str>,
+    /// Sent as the `Referer` of every request, the API rejects requests without one
+    pub referer: Box<str>,
+    pub user_agent: Box<str>,
+    /// Request timeout
+    pub timeout: Option<Duration>,
+    /// Maximum number of search results
+    pub search_limit: u32,
+}
+
+impl Default for AllAnimeClientConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "https://api.allanime.day/api".into(),
+            site_url: "https://allanime.day".into(),
+            referer: "https://allmanga.to".into(),
+            user_agent:
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0"
+                    .into(),
+            timeout: None,
+            search_limit: 40,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AllAnimeClient {
+    pub client: reqwest::Client,
+    pub config: AllAnimeClientConfig,
+}
+
+const SEARCH_QUERY: &str = "query(
+    $search: SearchInput
+    $limit: Int
+    $page: Int
+    $translationType: VaildTranslationTypeEnumType
+    $countryOrigin: VaildCountryOriginEnumType
+) {
+    shows(
+        search: $search
+        limit: $limit
+        page: $page
+        translationType: $translationType
+        countryOrigin: $countryOrigin
+    ) {
+        edges {
+            _id
+            name
+            englishName
+            nativeName
+            altNames
+            thumbnail
+            episodeCount
+            availableEpisodes
+        }
+    }
+}";
+
+const EPISODES_QUERY: &str = "query($showId: String!) {
+    show(_id: $showId) {
+        _id
+        availableEpisodesDetail
+    }
+}";
+
+const SOURCES_QUERY: &str = "query(
+    $showId: String!
+    $translationType: VaildTranslationTypeEnumType!
+    $episodeString: String!
+) {
+    episode(
+        showId: $showId
+        translationType: $translationType
+        episodeString: $episodeString
+    ) {
+        sourceUrls
+    }
+}";
+
+impl AllAnimeClient {
+    pub fn new(config: AllAnimeClientConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+    /// GET a URL with the headers the API expects
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.with_headers(self.client.get(url))
+    }
+    fn with_headers(&self, mut builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        builder = builder
+            .header(reqwest::header::REFERER, &*self.config.referer)
+            .header(reqwest::header::USER_AGENT, &*self.config.user_agent);
+        if let Some(dur) = self.config.timeout {
+            builder = builder.timeout(dur);
+        }
+        builder
+    }
+    async fn graphql<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> eyre::Result<T> {
+        #[derive(Deserialize)]
+        struct Response<T> {
+            data: Option<T>,
+            #[serde(default)]
+            errors: Vec<GraphQlError>,
+        }
+        #[derive(Deserialize)]
+        struct GraphQlError {
+            message: String,
+        }
+
+        let body = serde_json::json!({ "variables": variables, "query": query });
+        let resp = self
+            .with_headers(self.client.post(&*self.config.api_url))
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/json; charset=utf-8",
+            )
+            .body(body.to_string())
+            .send()
+            .await
+            .wrap_err("failed to query allanime")?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let resp: Response<T> =
+            serde_json::from_slice(&resp).wrap_err("failed to parse allanime response")?;
+        if !resp.errors.is_empty() {
+            let messages: Vec<_> = resp.errors.into_iter().map(|e| e.message).collect();
+            bail!("allanime API error: {}", messages.join("; "));
+        }
+        resp.data.ok_or_eyre("allanime response had no data")
+    }
+    /// Search for shows with episodes available in the given translation
+    pub async fn search(
+        &self,
+        query: &str,
+        translation: TranslationType,
+    ) -> eyre::Result<Vec<AllAnimeShow>> {
+        #[derive(Deserialize)]
+        struct Data {
+            shows: Shows,
+        }
+        #[derive(Deserialize)]
+        struct Shows {
+            edges: Vec<AllAnimeShow>,
+        }
+        let data: Data = self
+            .graphql(
+                SEARCH_QUERY,
+                serde_json::json!({
+                    "search": {
+                        "query": query,
+                        "allowAdult": false,
+                        "allowUnknown": false,
+                    },
+                    "limit": self.config.search_limit,
+                    "page": 1,
+                    "translationType": translation.as_str(),
+                    "countryOrigin": "ALL",
+                }),
+            )
+            .await?;
+        Ok(data.shows.edges)
+    }
+    /// List the episodes of a show available in each translation
+    pub async fn episodes(&self, show_id: &str) -> eyre::Result<EpisodeList> {
+        #[derive(Deserialize)]
+        struct Data {
+            show: Option<Show>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Show {
+            available_episodes_detail: EpisodeList,
+        }
+        let data: Data = self
+            .graphql(EPISODES_QUERY, serde_json::json!({ "showId": show_id }))
+            .await?;
+        let mut list = data
+            .show
+            .ok_or_else(|| eyre!("allanime show {show_id} not found"))?
+            .available_episodes_detail;
+        list.sort();
+        Ok(list)
+    }
+    /// The hoster links for an episode, in the order allanime lists them. See [`SourceUrl::priority`] for allanime's own ranking
+    pub async fn sources(
+        &self,
+        show_id: &str,
+        episode: &str,
+        translation: TranslationType,
+    ) -> eyre::Result<Vec<SourceUrl>> {
+        #[derive(Deserialize)]
+        struct Data {
+            episode: Option<Episode>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Episode {
+            source_urls: Vec<SourceUrl>,
+        }
+        let data: Data = self
+            .graphql(
+                SOURCES_QUERY,
+                serde_json::json!({
+                    "showId": show_id,
+                    "translationType": translation.as_str(),
+                    "episodeString": episode,
+                }),
+            )
+            .await?;
+        Ok(data
+            .episode
+            .ok_or_else(|| {
+                eyre!(
+                    "allanime has no {} episode {episode} for show {show_id}",
+                    translation.as_str()
+                )
+            })?
+            .source_urls)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::query_allanime;
+    use axum::{Json, Router, http::StatusCode, routing::post};
+    use serde_json::Value;
+
+    use crate::{
+        AllAnimeClient, AllAnimeClientConfig, AllanimeHosterName, AllanimeType, TranslationType,
+        quote_unquote_decrypt,
+    };
+
+    /// Serves the recorded API responses, picking one by the GraphQL operation in the request
+    async fn serve() -> AllAnimeClient {
+        async fn h_api(Json(body): Json<Value>) -> Result<Json<Value>, StatusCode> {
+            let query = body["query"].as_str().unwrap_or_default();
+            let vars = &body["variables"];
+            let fixture = if query.contains("shows(") {
+                include_str!("../fixtures/search.json")
+            } else if query.contains("show(") {
+                if vars["showId"] != "ReooPAxPMsHM4KPMY" {
+                    return Ok(Json(serde_json::json!({ "data": { "show": null } })));
+                }
+                include_str!("../fixtures/episodes.json")
+            } else if query.contains("episode(") {
+                if vars["episodeString"] != "10" {
+                    return Ok(Json(serde_json::json!({
+                        "errors": [{ "message": "episode not found" }],
+                        "data": { "episode": null },
+                    })));
+                }
+                include_str!("../fixtures/sources.json")
+            } else {
+                return Err(StatusCode::BAD_REQUEST);
+            };
+            Ok(Json(serde_json::from_str(fixture).unwrap()))
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, Router::new().route("/api", post(h_api)))
+                .await
+                .unwrap()
+        });
+        AllAnimeClient::new(AllAnimeClientConfig {
+            api_url: format!("http://{addr}/api").into(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn decrypt() {
+        assert_eq!(
+            quote_unquote_decrypt("--175948514e4c4f57175b54575b5307515c050f5c0a0c0f0b0f0c0e59")
+                .as_deref(),
+            Some("/apivtwo/clock?id=7d2473746a")
+        );
+        assert_eq!(quote_unquote_decrypt("https://example.com"), None);
+        // not hex, used to panic
+        assert_eq!(quote_unquote_decrypt("--zz"), None);
+    }
+
+    #[tokio::test]
+    async fn search() {
+        let client = serve().await;
+        let shows = client
+            .search("bloom into you", TranslationType::Sub)
+            .await
+            .unwrap();
+        assert_eq!(shows.len(), 2);
+        let show = &shows[0];
+        assert_eq!(&*show.id, "ReooPAxPMsHM4KPMY");
+        assert_eq!(&*show.name, "Yagate Kimi ni Naru");
+        assert_eq!(show.english_name.as_deref(), Some("Bloom Into You"));
+        assert_eq!(show.episode_count, Some(13));
+        assert_eq!(show.available_episodes.get(TranslationType::Sub), 13);
+        assert_eq!(show.available_episodes.get(TranslationType::Dub), 13);
+        assert!(show.names().any(|v| v == "やがて君になる"));
+        // specials have no episode count
+        assert_eq!(shows[1].episode_count, None);
+    }
+
+    #[tokio::test]
+    async fn episodes() {
+        let client = serve().await;
+        let episodes = client.episodes("ReooPAxPMsHM4KPMY").await.unwrap();
+        assert_eq!(episodes.get(TranslationType::Sub).len(), 14);
+        assert_eq!(&*episodes.sub[0], "1");
+        assert_eq!(&*episodes.sub[6], "6.5");
+        assert_eq!(&*episodes.sub[13], "13");
+        assert_eq!(episodes.dub.len(), 13);
+        assert!(episodes.raw.is_empty());
+
+        assert!(client.episodes("missing").await.is_err());
+    }
 
     #[tokio::test]
-    async fn test() {
-        query_allanime("bloom into you".into(), 10).await.unwrap();
+    async fn sources() {
+        let client = serve().await;
+        let sources = client
+            .sources("ReooPAxPMsHM4KPMY", "10", TranslationType::Sub)
+            .await
+            .unwrap();
+        assert_eq!(sources.len(), 6);
+        assert_eq!(sources[0].source_name, AllanimeHosterName::Default);
+        assert_eq!(sources[0].url(), "/apivtwo/clock?id=default");
+        assert_eq!(sources[0].source_type, Some(AllanimeType::Player));
+        assert_eq!(sources[1].source_name, AllanimeHosterName::SMp4);
+        assert_eq!(sources[3].source_name, AllanimeHosterName::YtMp4);
+        assert!(sources[3].url().starts_with("https://"));
+        assert_eq!(
+            sources[4].source_name,
+            AllanimeHosterName::Other("Fm-Hls".into())
+        );
+        // unobfuscated URLs are passed through
+        assert_eq!(sources[5].source_type, Some(AllanimeType::IFrame));
+        assert_eq!(sources[5].url(), "https://streamsb.example/e/abc123");
+
+        let err = client
+            .sources("ReooPAxPMsHM4KPMY", "99", TranslationType::Sub)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("episode not found"));
     }
 }
@@ -0,0 +1,532 @@
+use std::collections::BTreeMap;
+
+use eyre::{Context, OptionExt, bail};
+use futures::future::join_all;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{AllAnimeClient, AllanimeHosterName, AllanimeType, SourceUrl};
+
+/// A directly playable stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamLink {
+    pub url: Box<str>,
+    pub hoster: AllanimeHosterName,
+    pub kind: StreamKind,
+    /// Vertical resolution, if known
+    pub resolution: Option<u32>,
+    /// Headers that must be sent when requesting [`StreamLink::url`], e.g. `Referer`
+    pub headers: BTreeMap<Box<str>, Box<str>>,
+    pub subtitles: Vec<Subtitle>,
+    /// allanime's ranking of the hoster this came from, higher is better
+    pub priority: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamKind {
+    /// A single file
+    File,
+    /// An HLS media playlist
+    Hls,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subtitle {
+    /// Language code, e.g. `en`
+    pub lang: Box<str>,
+    pub label: Option<Box<str>>,
+    pub url: Box<str>,
+}
+
+/// Response of `/apivtwo/clock.json`, which allanime's own hosters are resolved through
+#[derive(Debug, Deserialize)]
+struct Clock {
+    links: Vec<ClockLink>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClockLink {
+    link: Box<str>,
+    resolution_str: Option<Box<str>>,
+    #[serde(default)]
+    hls: bool,
+    #[serde(default)]
+    subtitles: Vec<ClockSubtitle>,
+    #[serde(default)]
+    headers: BTreeMap<Box<str>, Box<str>>,
+}
+#[derive(Debug, Deserialize)]
+struct ClockSubtitle {
+    lang: Box<str>,
+    label: Option<Box<str>>,
+    src: Box<str>,
+}
+
+/// How the links of a hoster are turned into streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolver {
+    /// The source URL is already the stream
+    Direct,
+    /// Resolved through `clock.json`, which lists the files directly
+    ClockFiles,
+    /// Resolved through `clock.json`, whose links are wixmp "repackager" master playlists that can be turned into one file per resolution
+    ClockWixmp,
+    /// Resolved through `clock.json`, whose links are HLS master playlists
+    ClockHls,
+}
+
+impl AllanimeHosterName {
+    fn resolver(&self) -> Option<Resolver> {
+        use AllanimeHosterName::*;
+        Some(match self {
+            Default => Resolver::ClockWixmp,
+            SMp4 => Resolver::ClockFiles,
+            LufMp4 | SiHls | AcHls | PnHls => Resolver::ClockHls,
+            Other(name) if name.ends_with("-Hls") => Resolver::ClockHls,
+            YtMp4 => Resolver::Direct,
+            // third party embeds (mp4upload, ok.ru, vidstreaming, ...), which would need scraping each site
+            Ok | Mp4 | Ac | Ak | Kir | Rab | UvMp4 | VidMp4 | Other(_) => return None,
+        })
+    }
+}
+
+/// Parses resolutions like `1080p` or `1920x1080`
+fn parse_resolution(s: &str) -> Option<u32> {
+    let s = s.trim();
+    match s.split_once('x') {
+        Some((_, height)) => height.parse().ok(),
+        None => s.strip_suffix('p')?.parse().ok(),
+    }
+}
+
+/// Expands a wixmp repackager link (`https://repackager.wixmp.com/{host}/{path}/,1080p,720p,/mp4/file.mp4.urlset/master.m3u8`) into one direct file link per resolution
+fn expand_wixmp(link: &str) -> Option<Vec<(u32, String)>> {
+    let rest = link.split_once("repackager.wixmp.com/")?.1;
+    let base = &rest[..rest.find(".urlset")?];
+    let (prefix, rest) = base.split_once("/,")?;
+    let (resolutions, suffix) = rest.split_once(",/")?;
+    Some(
+        resolutions
+            .split(',')
+            .filter(|v| !v.is_empty())
+            .map(|res| {
+                (
+                    parse_resolution(res).unwrap_or_default(),
+                    format!("https://{prefix}/{res}/{suffix}"),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Variants of an HLS master playlist as `(height, uri)`, with the URIs resolved against the playlist URL.
+/// Returns an empty list for media playlists.
+fn parse_master_playlist(base: &Url, playlist: &str) -> Vec<(Option<u32>, String)> {
+    let mut variants = Vec::new();
+    let mut lines = playlist.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") else {
+            continue;
+        };
+        let resolution = attrs
+            .split(',')
+            .find_map(|attr| attr.strip_prefix("RESOLUTION="))
+            .and_then(parse_resolution);
+        let Some(uri) = lines.find(|v| !v.is_empty() && !v.starts_with('#')) else {
+            break;
+        };
+        if let Ok(uri) = base.join(uri) {
+            variants.push((resolution, uri.to_string()));
+        }
+    }
+    variants
+}
+
+/// Orders streams best first: highest resolution, then the hoster allanime ranks highest
+pub fn rank(links: &mut [StreamLink]) {
+    links.sort_by(|a, b| {
+        b.resolution
+            .cmp(&a.resolution)
+            .then(b.priority.total_cmp(&a.priority))
+    });
+}
+
+impl AllAnimeClient {
+    /// Resolve every source that has a resolver, returning all streams found ranked with [`rank`].
+    /// Sources that fail to resolve are skipped.
+    pub async fn resolve_all(&self, sources: &[SourceUrl]) -> Vec<StreamLink> {
+        let mut links = Vec::new();
+        for (source, res) in sources
+            .iter()
+            .zip(join_all(sources.iter().map(|s| self.resolve(s))).await)
+        {
+            match res {
+                Ok(v) => links.extend(v),
+                Err(e) => log::debug!(
+                    "could not resolve allanime source {:?}: {e:#}",
+                    source.source_name
+                ),
+            }
+        }
+        rank(&mut links);
+        links
+    }
+    /// Resolve a single hoster link into the streams it offers
+    pub async fn resolve(&self, source: &SourceUrl) -> eyre::Result<Vec<StreamLink>> {
+        let Some(resolver) = source.source_name.resolver() else {
+            if source.source_type == Some(AllanimeType::IFrame) {
+                bail!("{:?} is an embedded player", source.source_name);
+            }
+            bail!("no resolver for {:?}", source.source_name);
+        };
+        let url = source.url();
+        let stream = |url: String, kind, resolution, headers, subtitles| StreamLink {
+            url: url.into(),
+            hoster: source.source_name.clone(),
+            kind,
+            resolution,
+            headers,
+            subtitles,
+            priority: source.priority,
+        };
+
+        if resolver == Resolver::Direct {
+            return Ok(vec![stream(
+                url.into_owned(),
+                StreamKind::File,
+                None,
+                self.default_headers(),
+                Vec::new(),
+            )]);
+        }
+
+        let clock = self.clock(&url).await?;
+        let mut out = Vec::new();
+        for link in clock.links {
+            let mut headers = self.default_headers();
+            headers.extend(link.headers);
+            let subtitles: Vec<_> = link
+                .subtitles
+                .into_iter()
+                .map(|v| Subtitle {
+                    lang: v.lang,
+                    label: v.label,
+                    url: v.src,
+                })
+                .collect();
+            let resolution = link.resolution_str.as_deref().and_then(parse_resolution);
+
+            let files = match resolver {
+                Resolver::ClockWixmp => expand_wixmp(&link.link),
+                _ => None,
+            };
+            match (resolver, files) {
+                (_, Some(files)) => {
+                    out.extend(files.into_iter().map(|(res, url)| {
+                        stream(
+                            url,
+                            StreamKind::File,
+                            Some(res).filter(|v| *v != 0),
+                            headers.clone(),
+                            subtitles.clone(),
+                        )
+                    }));
+                }
+                (Resolver::ClockHls, None) => {
+                    let master = Url::parse(&link.link)
+                        .wrap_err_with(|| format!("invalid HLS playlist url {:?}", link.link))?;
+                    let mut req = self.client.get(master.clone());
+                    for (k, v) in &headers {
+                        req = req.header(&**k, &**v);
+                    }
+                    let playlist = req.send().await?.error_for_status()?.text().await?;
+                    let variants = parse_master_playlist(&master, &playlist);
+                    if variants.is_empty() {
+                        // already a media playlist
+                        out.push(stream(
+                            link.link.into(),
+                            StreamKind::Hls,
+                            resolution,
+                            headers,
+                            subtitles,
+                        ));
+                    } else {
+                        out.extend(variants.into_iter().map(|(res, url)| {
+                            stream(
+                                url,
+                                StreamKind::Hls,
+                                res,
+                                headers.clone(),
+                                subtitles.clone(),
+                            )
+                        }));
+                    }
+                }
+                _ => {
+                    let kind = if link.hls {
+                        StreamKind::Hls
+                    } else {
+                        StreamKind::File
+                    };
+                    out.push(stream(
+                        link.link.into(),
+                        kind,
+                        resolution,
+                        headers,
+                        subtitles,
+                    ));
+                }
+            }
+        }
+        Ok(out)
+    }
+    fn default_headers(&self) -> BTreeMap<Box<str>, Box<str>> {
+        BTreeMap::from([
+            ("Referer".into(), self.config.referer.clone()),
+            ("User-Agent".into(), self.config.user_agent.clone()),
+        ])
+    }
+    /// Fetch `clock.json` for a `/apivtwo/clock?id=...` source path
+    async fn clock(&self, path: &str) -> eyre::Result<Clock> {
+        let path = path
+            .strip_prefix("/apivtwo/clock?")
+            .ok_or_eyre("expected an allanime clock link")?;
+        let url = format!(
+            "{}/apivtwo/clock.json?{path}",
+            self.config.site_url.trim_end_matches('/')
+        );
+        let body = self
+            .get(&url)
+            .send()
+            .await
+            .wrap_err("failed to request allanime clock")?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        serde_json::from_slice(&body).wrap_err("failed to parse allanime clock response")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        extract::{Query, State},
+        http::{HeaderMap, StatusCode},
+        routing::get,
+    };
+    use reqwest::Url;
+    use serde::Deserialize;
+
+    use crate::{AllAnimeClient, AllAnimeClientConfig, AllanimeHosterName, SourceUrl};
+
+    use super::{StreamKind, expand_wixmp, parse_master_playlist};
+
+    fn sources() -> Vec<SourceUrl> {
+        #[derive(Deserialize)]
+        struct Response {
+            data: Data,
+        }
+        #[derive(Deserialize)]
+        struct Data {
+            episode: Episode,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Episode {
+            source_urls: Vec<SourceUrl>,
+        }
+        serde_json::from_str::<Response>(include_str!("../fixtures/sources.json"))
+            .unwrap()
+            .data
+            .episode
+            .source_urls
+    }
+
+    /// Serves the recorded clock responses and playlists. `{base}` in the fixtures is replaced with the server URL
+    async fn serve() -> AllAnimeClient {
+        #[derive(Deserialize)]
+        struct Clock {
+            id: String,
+        }
+        async fn h_clock(
+            State(base): State<String>,
+            Query(Clock { id }): Query<Clock>,
+        ) -> Result<String, StatusCode> {
+            let fixture = match &*id {
+                "default" => include_str!("../fixtures/clock_default.json"),
+                "smp4" => include_str!("../fixtures/clock_smp4.json"),
+                "lufmp4" => include_str!("../fixtures/clock_lufmp4.json"),
+                "fmhls" => include_str!("../fixtures/clock_fmhls.json"),
+                _ => return Err(StatusCode::NOT_FOUND),
+            };
+            Ok(fixture.replace("{base}", &base))
+        }
+        async fn h_master(headers: HeaderMap) -> Result<&'static str, StatusCode> {
+            // the clock response asks for this referer
+            if headers
+                .get("referer")
+                .is_none_or(|v| v != "https://hianime.example/")
+            {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            Ok(include_str!("../fixtures/master.m3u8"))
+        }
+        async fn h_media() -> &'static str {
+            "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:10.0,\nseg0.ts\n#EXT-X-ENDLIST\n"
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new()
+            .route("/apivtwo/clock.json", get(h_clock))
+            .route("/hls/master.m3u8", get(h_master))
+            .route("/hls/fm/index.m3u8", get(h_media))
+            .with_state(base.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        AllAnimeClient::new(AllAnimeClientConfig {
+            site_url: base.into(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn wixmp() {
+        let files = expand_wixmp("https://repackager.wixmp.com/video.wixstatic.com/video/abc_def/,1080p,720p,480p,/mp4/file.mp4.urlset/master.m3u8").unwrap();
+        assert_eq!(
+            files,
+            [
+                (
+                    1080,
+                    "https://video.wixstatic.com/video/abc_def/1080p/mp4/file.mp4".to_string()
+                ),
+                (
+                    720,
+                    "https://video.wixstatic.com/video/abc_def/720p/mp4/file.mp4".to_string()
+                ),
+                (
+                    480,
+                    "https://video.wixstatic.com/video/abc_def/480p/mp4/file.mp4".to_string()
+                ),
+            ]
+        );
+        assert_eq!(expand_wixmp("https://example.com/video.mp4"), None);
+    }
+
+    #[test]
+    fn master_playlist() {
+        let base = Url::parse("https://cdn.example/hls/master.m3u8").unwrap();
+        let variants = parse_master_playlist(&base, include_str!("../fixtures/master.m3u8"));
+        assert_eq!(
+            variants,
+            [
+                (
+                    Some(1080),
+                    "https://cdn.example/hls/1080/index.m3u8".to_string()
+                ),
+                (
+                    Some(720),
+                    "https://cdn.example/hls/720/index.m3u8".to_string()
+                ),
+                (
+                    Some(360),
+                    "https://other.example/360/index.m3u8".to_string()
+                ),
+            ]
+        );
+        assert!(parse_master_playlist(&base, "#EXTM3U\n#EXTINF:10.0,\nseg0.ts\n").is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolve_hosters() {
+        let client = serve().await;
+        let sources = sources();
+        let by_name = |name: AllanimeHosterName| {
+            sources
+                .iter()
+                .find(|v| v.source_name == name)
+                .unwrap()
+                .clone()
+        };
+
+        let default = client
+            .resolve(&by_name(AllanimeHosterName::Default))
+            .await
+            .unwrap();
+        let resolutions: Vec<_> = default.iter().map(|v| v.resolution).collect();
+        assert_eq!(resolutions, [Some(1080), Some(720), Some(480)]);
+        assert!(default.iter().all(|v| v.kind == StreamKind::File));
+        assert_eq!(default[0].subtitles.len(), 1);
+        assert_eq!(&*default[0].subtitles[0].lang, "en");
+
+        let smp4 = client
+            .resolve(&by_name(AllanimeHosterName::SMp4))
+            .await
+            .unwrap();
+        assert_eq!(smp4.len(), 2);
+        assert_eq!(smp4[0].resolution, Some(1080));
+        assert_eq!(
+            smp4[0].headers.get("Referer").map(|v| &**v),
+            Some("https://allmanga.to")
+        );
+
+        let luf = client
+            .resolve(&by_name(AllanimeHosterName::LufMp4))
+            .await
+            .unwrap();
+        assert_eq!(luf.len(), 3);
+        assert!(luf.iter().all(|v| v.kind == StreamKind::Hls));
+        // headers from the clock response take precedence
+        assert_eq!(
+            luf[0].headers.get("Referer").map(|v| &**v),
+            Some("https://hianime.example/")
+        );
+        assert!(luf[0].url.ends_with("/hls/1080/index.m3u8"));
+
+        let fm = client
+            .resolve(&by_name(AllanimeHosterName::Other("Fm-Hls".into())))
+            .await
+            .unwrap();
+        assert_eq!(fm.len(), 1);
+        assert_eq!(fm[0].resolution, Some(720));
+
+        let yt = client
+            .resolve(&by_name(AllanimeHosterName::YtMp4))
+            .await
+            .unwrap();
+        assert_eq!(
+            &*yt[0].url,
+            "https://tools.fast4speed.rsvp//media9/videos/ReooPAxPMsHM4KPMY/sub/10"
+        );
+
+        assert!(
+            client
+                .resolve(&by_name(AllanimeHosterName::Other("Sb".into())))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_all_ranked() {
+        let client = serve().await;
+        let links = client.resolve_all(&sources()).await;
+        // 3 wixmp + 2 sharepoint + 3 hls variants + 1 hls + 1 direct, the embed is skipped
+        assert_eq!(links.len(), 10);
+        let top: Vec<_> = links
+            .iter()
+            .take(3)
+            .map(|v| (v.resolution, v.hoster.clone()))
+            .collect();
+        assert_eq!(
+            top,
+            [
+                (Some(1080), AllanimeHosterName::Default),
+                (Some(1080), AllanimeHosterName::SMp4),
+                (Some(1080), AllanimeHosterName::LufMp4),
+            ]
+        );
+        // unknown resolutions rank last
+        assert_eq!(links.last().unwrap().hoster, AllanimeHosterName::YtMp4);
+    }
+}
@@ -5,11 +5,12 @@ use log::error;
 use rand::random;
 use sled::Tree;
 
-use crate::{show::Show, show::ShowId};
+use crate::show::{Show, ShowId, ShowPrefs};
 #[allow(unused)]
 pub struct MainDb {
     db: sled::Db,
     pub shows: TypedTree<ShowId, Show>,
+    pub show_prefs: TypedTree<ShowId, ShowPrefs>,
 }
 
 pub struct TypedTree<K: Into<u64> + From<u64> + Copy, V: Encode + Decode<()>> {
@@ -29,7 +30,7 @@ impl<K: Into<u64> + From<u64> + Copy, V: Encode + Decode<()>> TypedTree<K, V> {
                             .expect("keys to be at least 4 bytes long"),
                     );
                     let (v, _) = bincode::decode_from_slice(&v, bincode::config::standard())
-                        .expect("show to be well formed");
+                        .expect("entry to be well formed");
                     cache.insert(k, v);
                 }
                 Err(e) => error!("failed to load a show from the database: {e}"),
@@ -83,6 +84,13 @@ impl<K: Into<u64> + From<u64> + Copy, V: Encode + Decode<()>> TypedTree<K, V> {
         }
     }
 
+    /// Inserts or replaces the value under an existing key, e.g. one taken from another tree
+    pub fn set(&mut self, key: K, value: V) {
+        let k = key.into();
+        self.write(k, &value);
+        let _ = self.cache.insert(k, value);
+    }
+
     pub fn insert(&mut self, value: V) -> K {
         let id = self.available_id();
         self.write(id, &value);
@@ -139,6 +147,14 @@ impl MainDb {
         let db = sled::open(p).expect("database to open");
         let shows = db.open_tree(b"shows").expect("shows tree to open");
         let shows = TypedTree::new(shows);
-        Self { db, shows }
+        let show_prefs = db
+            .open_tree(b"show_prefs")
+            .expect("show_prefs tree to open");
+        let show_prefs = TypedTree::new(show_prefs);
+        Self {
+            db,
+            shows,
+            show_prefs,
+        }
     }
 }
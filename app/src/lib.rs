@@ -2,11 +2,13 @@ use std::{
     collections::{HashMap, HashSet},
     fs::{self, File},
     iter::zip,
+    net::{Ipv4Addr, SocketAddr},
     num::{NonZero, NonZeroU32},
     path::{Path, PathBuf},
     sync::Arc,
 };
 
+use ::allanime::AllAnimeClient;
 use ::nyaa::NyaaClient;
 use anilist_moe::models::Anime;
 use bincode::{Decode, Encode};
@@ -33,7 +35,7 @@ use iced_runtime::futures::backend::default::time::every;
 #[cfg(not(test))]
 use std::time::Duration;
 
-use rqstream::Rqstream;
+use rqstream::{ReadAheadConfig, Rqstream, RqstreamConfig, StorageConfig};
 use serde::{Deserialize, Serialize};
 
 use tokio::sync::{Mutex, OnceCell, OwnedMutexGuard};
@@ -46,10 +48,14 @@ use tokio::sync::mpsc::UnboundedSender;
 use crate::{
     db::MainDb,
     discord::DiscordPresence,
-    media::{AnyMedia, Media, PlayRequest, PlayableMedia, PlayingMedia},
+    library::Library,
+    media::{
+        AnyMedia, LiveMediaHandle, Media, PlayRequest, PlayableMedia, PlayingMedia,
+        local::LocalMedia, pool::MediaPool,
+    },
     player::{PlayerSession, PlayerSessionMpv},
-    show::{EpochInstant, Show, ShowId, WatchEvent},
-    source::{Source, nyaa::Nyaa},
+    show::{EpochInstant, Show, ShowId, ShowPrefs, WatchEvent},
+    source::{Source, TranslationPreference, allanime::AllAnime, nyaa::Nyaa},
     util::NoDebug,
 };
 
@@ -58,6 +64,7 @@ use crate::{
 // TODO support MAL, list/tracker abstraction
 pub mod anilist;
 pub mod db;
+pub mod library;
 pub mod player;
 pub mod show;
 
@@ -85,20 +92,50 @@ pub enum NameKind {
 pub struct Config {
     pub default_source_type: SourceType,
     pub preferred_name_kind: NameKind,
+    /// translations to look for, unless overridden for a show
+    pub translation: TranslationPreference,
     pub anilist: anilist::Config,
     pub nyaa: source::nyaa::Config,
+    pub allanime: source::allanime::Config,
     pub player: PlayerConfig,
+    pub stream: StreamConfig,
+    pub library: library::Config,
     pub db_path: Option<PathBuf>,
 }
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PlayerConfig {
     //TODO player path etc
     pub max_remaining_to_complete: u32,
+    /// bytes of media that stopped playing to keep paused for quick reentry, 0 stops everything right away
+    pub pause_pool_bytes: u64,
 }
 impl Default for PlayerConfig {
     fn default() -> Self {
         Self {
             max_remaining_to_complete: 120,
+            pause_pool_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// address the local torrent stream server listens on, port 0 picks a free one
+    pub bind_addr: SocketAddr,
+    /// where torrents too large to stream from memory are cached, `rqstream` in the cache directory if unset.
+    /// Emptied on startup
+    pub cache_dir: Option<PathBuf>,
+    /// rate limits, seeding and the like for the torrent session
+    pub torrent: RqstreamConfig,
+}
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+            cache_dir: None,
+            torrent: RqstreamConfig::default(),
         }
     }
 }
@@ -107,10 +144,17 @@ impl Default for PlayerConfig {
 pub enum SourceType {
     #[default]
     RqNyaa,
-    // TODO etc
+    /// direct streams from AllAnime's hosters
+    AllAnime,
 }
 
 impl Config {
+    /// The translation preference for a show with `prefs`. A show's own preference replaces the global one entirely
+    pub fn translation_for(&self, prefs: Option<&ShowPrefs>) -> TranslationPreference {
+        prefs
+            .and_then(|v| v.translation.clone())
+            .unwrap_or_else(|| self.translation.clone())
+    }
     fn load(file: impl AsRef<Path>) -> Self {
         let p = file.as_ref();
         if !p.exists() {
@@ -133,7 +177,11 @@ pub struct Monsoon {
 
 pub struct LiveState {
     pub nyaa: NyaaClient,
-    pub rqstream: Arc<OnceCell<Arc<Rqstream>>>,
+    pub allanime: AllAnimeClient,
+    pub rqstream: Arc<LazyRqstream>,
+    pub library: Library,
+    /// media that stopped playing, paused
+    pub media_pool: MediaPool,
     pub current_player_session: Option<PlayerSession>,
     pub ani_client: Arc<anilist_moe::AniListClient>,
     pub current_add_query: Option<AddQuery>,
@@ -150,18 +198,61 @@ pub struct AddQuery {
     pub candidates_dirty: bool,
     pub candidates: Vec<(Option<image::Handle>, Anime)>,
 }
+/// The torrent session, only started once something is actually streamed
+pub struct LazyRqstream {
+    cell: OnceCell<Arc<Rqstream>>,
+    bind_addr: SocketAddr,
+    cache_dir: PathBuf,
+    config: RqstreamConfig,
+}
+impl LazyRqstream {
+    fn new(bind_addr: SocketAddr, cache_dir: PathBuf, config: RqstreamConfig) -> Self {
+        // anything in it was left behind by a session that did not get to stop its streams, e.g. because it crashed
+        if let Err(e) = fs::remove_dir_all(&cache_dir)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            log::warn!("failed to clear stream cache {}: {e}", cache_dir.display());
+        }
+        Self {
+            cell: OnceCell::new(),
+            bind_addr,
+            cache_dir,
+            config,
+        }
+    }
+    /// The session, if it was started
+    pub fn get(&self) -> Option<&Arc<Rqstream>> {
+        self.cell.get()
+    }
+}
+
 pub(crate) const FAILED_LOAD_IMAGE: &[u8] = include_bytes!("../itbroke.jpg");
 
 impl LiveState {
-    fn new(conf: &Config) -> Self {
+    fn new(conf: &Config, dirs: &ProjectDirs) -> Self {
         Self {
-            rqstream: Arc::new(OnceCell::new()),
+            rqstream: Arc::new(LazyRqstream::new(
+                conf.stream.bind_addr,
+                conf.stream
+                    .cache_dir
+                    .clone()
+                    .unwrap_or_else(|| dirs.cache_dir().join("rqstream")),
+                conf.stream.torrent.clone(),
+            )),
+            library: Library::new(
+                conf.library
+                    .dir
+                    .clone()
+                    .unwrap_or_else(|| dirs.data_dir().join("library")),
+            ),
+            media_pool: MediaPool::default(),
             // todo auth
             ani_client: Arc::new(anilist_moe::AniListClient::new()),
             current_add_query: None,
             couldnt_load_image: image::Handle::from_bytes(FAILED_LOAD_IMAGE),
             current_player_session: None,
             nyaa: NyaaClient::new(conf.nyaa.nyaa.clone()),
+            allanime: AllAnimeClient::new(conf.allanime.allanime.clone()),
             show_source_dedupe: HashMap::new(),
             #[cfg(feature = "discord")]
             discord_rpc: DiscordPresence::spawn(),
@@ -172,9 +263,22 @@ impl LiveState {
     pub fn get_rqstream(
         &self,
     ) -> impl Future<Output = eyre::Result<Arc<Rqstream>>> + Send + 'static {
-        let a2 = Arc::clone(&self.rqstream);
+        Self::init_rqstream(Arc::clone(&self.rqstream))
+    }
+    /// Like [`LiveState::get_rqstream`], for futures that only hold on to the cell and start the session once they actually need it
+    pub(crate) fn init_rqstream(
+        rq: Arc<LazyRqstream>,
+    ) -> impl Future<Output = eyre::Result<Arc<Rqstream>>> + Send + 'static {
         async move {
-            a2.get_or_try_init(|| Rqstream::create("127.0.0.1:9000"))
+            rq.cell
+                .get_or_try_init(|| {
+                    Rqstream::create_with_config(
+                        rq.bind_addr,
+                        StorageConfig::hybrid(rq.cache_dir.clone()),
+                        ReadAheadConfig::default(),
+                        rq.config.clone(),
+                    )
+                })
                 .await
                 .map(Arc::clone)
                 .map_err(|v| eyre!(Box::new(v)))
@@ -197,7 +301,7 @@ impl Monsoon {
         };
         let db = MainDb::open(db_path);
         let (main_window_id, task) = iced_runtime::window::open(Default::default());
-        let live = LiveState::new(&config);
+        let live = LiveState::new(&config, &dirs);
 
         (
             Self {
@@ -326,12 +430,12 @@ impl Monsoon {
                 return Ok(Message::Error(err));
             }
 
-            session
-                .play(match &media.playable {
-                    media::Playable::Url(s) => s.clone(),
-                    media::Playable::File(path_buf) => path_buf.to_string_lossy().into(),
-                })
-                .await?;
+            match &media.playable {
+                media::Playable::Url { url, headers } => session.play(url.clone(), headers).await?,
+                media::Playable::File(path_buf) => {
+                    session.play(path_buf.to_string_lossy().into(), &[]).await?
+                }
+            }
             session.seek(pos).await?;
 
             Ok::<_, eyre::Report>(Message::Session(ModifySession::SetPlaying(PlayingMedia {
@@ -380,6 +484,7 @@ impl Monsoon {
                     .collect::<Vec<_>>()
                     .into_iter()
                     .for_each(|id| self.load_thumbnail(id, &mut tasks));
+                tasks.push(Message::Library(ModifyLibrary::Refresh));
             }
             Message::AddAnime(a) => {
                 match a {
@@ -485,22 +590,28 @@ impl Monsoon {
                     let _ = self.thumbnails.insert(show_id, handle);
                 }
                 ModifyShow::RequestRemove => {
-                    let _ = self.db.shows.drop(show_id);
+                    if let Some(show) = self.db.shows.drop(show_id) {
+                        library::downloads(&show)
+                            .for_each(|v| tasks.push(delete_download(v.dir.clone())));
+                    }
+                    let _ = self.db.show_prefs.drop(show_id);
                 }
                 ModifyShow::SetWatched(ep, watched) => {
                     let _ = self.db.shows.update_with(show_id, |show| {
-                        match show.watched_episodes.get_mut(ep as usize) {
-                            Some(r) => *r = watched,
-                            None => log::warn!(
+                        if !show.set_watched(ep, watched) {
+                            log::warn!(
                                 "tried to set a out-of-bounds episode as watched or unwatched"
-                            ),
+                            );
                         }
                     });
+                    tasks.push(Message::Library(ModifyLibrary::Refresh));
                 }
                 ModifyShow::FlushSourceCache => {
                     let _ = self.db.shows.update_with(show_id, |show| {
-                        show.media_cache.clear();
+                        // downloads are not re-fetched, and would be lost track of
+                        show.media_cache.retain(|v| matches!(v, AnyMedia::Local(_)));
                     });
+                    self.live.show_source_dedupe.remove(&show_id);
                 }
                 ModifyShow::CacheMedia(any_media) => {
                     let _ = self
@@ -513,6 +624,16 @@ impl Monsoon {
                     self.more_info_windows.insert(id, show_id);
                     tasks.push(task.discard());
                 }
+                ModifyShow::SetTranslation(translation) => {
+                    let mut prefs = self.db.show_prefs.get(show_id).cloned().unwrap_or_default();
+                    prefs.translation = translation;
+                    self.db.show_prefs.set(show_id, prefs);
+                    // cached sources were selected for the previous preference
+                    let _ = self.db.shows.update_with(show_id, |show| {
+                        show.media_cache.retain(|v| matches!(v, AnyMedia::Local(_)));
+                    });
+                    self.live.show_source_dedupe.remove(&show_id);
+                }
                 ModifyShow::SetNumEpisodes(non_zero) => {
                     let _ = self.db.shows.update_with(show_id, move |v| {
                         v.num_episodes = non_zero;
@@ -536,6 +657,12 @@ impl Monsoon {
                 }
                 ModifySession::SetPlaying(play) => {
                     tasks.extend(self.cleanup_show());
+                    // the new stream keeps the torrent warm by itself now
+                    if let Some(handle) = &play.media.lifecycle
+                        && let Some(pooled) = self.live.media_pool.take(handle.identifier())
+                    {
+                        tasks.push(destroy_media(pooled));
+                    }
 
                     if let Some(session) = &mut self.live.current_player_session {
                         #[cfg(feature = "discord")]
@@ -577,8 +704,9 @@ impl Monsoon {
                             })
                         {
                             let _ = self.db.shows.update_with(media.show, |v| {
-                                v.watched_episodes[media.episode_idx as usize] = true;
+                                v.set_watched(media.episode_idx, true);
                             });
+                            tasks.push(Message::Library(ModifyLibrary::Refresh));
                         }
                     }
                 }
@@ -619,53 +747,100 @@ impl Monsoon {
                         .ok_or_eyre("tried to play a show not in DB")
                 );
                 let name = show.get_preferred_name(&self.config);
-                for media in show.media_cache.iter() {
-                    if media.has_ep(req.episode_idx) {
-                        let fut = media.play(&req, &mut self.live).unwrap();
-                        log::trace!(
-                            "using cached source {media:?} for episode {} of show {name}",
-                            req.episode_idx,
+                // downloads play without the network, so they go first
+                let cached = show
+                    .media_cache
+                    .iter()
+                    .filter(|v| v.has_ep(req.episode_idx))
+                    .min_by_key(|v| !matches!(v, AnyMedia::Local(_)));
+                if let Some(media) = cached {
+                    let fut = media.play(&req, &mut self.live).unwrap();
+                    log::trace!(
+                        "using cached source {media:?} for episode {} of show {name}",
+                        req.episode_idx,
+                    );
+                    tasks.push(
+                        async move {
+                            let media = Box::into_pin(fut).await?;
+                            Ok::<_, eyre::Report>(Message::Play(req, media))
+                        }
+                        .into_task(),
+                    );
+
+                    break 'play;
+                }
+                log::info!("failed to locate cached source for show {name}");
+                let translation = self
+                    .config
+                    .translation_for(self.db.show_prefs.get(req.show));
+                match self.config.default_source_type {
+                    SourceType::RqNyaa => {
+                        let episode_query = Nyaa.query(
+                            &mut self.live,
+                            &self.config,
+                            show,
+                            &translation,
+                            Some(req.episode_idx),
                         );
+                        let batch_query =
+                            Nyaa.query(&mut self.live, &self.config, show, &translation, None);
                         tasks.push(
                             async move {
-                                let media = Box::into_pin(fut).await?;
-                                Ok::<_, eyre::Report>(Message::Play(req, media))
+                                let episode = episode_query.await?;
+                                log::trace!(
+                                    "nyaa searched for direct episode, options: {episode:#?}"
+                                );
+                                let selected_item = match episode.into_iter().next() {
+                                    Some(v) => Some(v),
+                                    None => {
+                                        let batch = batch_query.await?;
+                                        log::trace!(
+                                            "nyaa fell back to batch searching, options: {batch:#?}"
+                                        );
+                                        batch.into_iter().next()
+                                    }
+                                }
+                                .ok_or_eyre("nyaa failed to select a source")?;
+
+                                log::trace!("nyaa selected item {selected_item:?}");
+                                let playable_fut =
+                                    Box::into_pin(NoDebug::into_inner(selected_item.media));
+
+                                Ok::<_, eyre::Report>(Message::MakePlayable(
+                                    req,
+                                    playable_fut.await?,
+                                ))
                             }
                             .into_task(),
+                        )
+                    }
+                    SourceType::AllAnime => {
+                        let episode_query = AllAnime.query(
+                            &mut self.live,
+                            &self.config,
+                            show,
+                            &translation,
+                            Some(req.episode_idx),
                         );
+                        tasks.push(
+                            async move {
+                                let selected_item = episode_query
+                                    .await?
+                                    .into_iter()
+                                    .next()
+                                    .ok_or_eyre("allanime has no such episode")?;
+                                log::trace!("allanime selected item {selected_item:?}");
+                                let playable_fut =
+                                    Box::into_pin(NoDebug::into_inner(selected_item.media));
 
-                        break 'play;
-                    }
-                }
-                log::info!("failed to locate cached source for show {name}");
-                let episode_query =
-                    Nyaa.query(&mut self.live, &self.config, show, Some(req.episode_idx));
-                let batch_query = Nyaa.query(&mut self.live, &self.config, show, None);
-                match self.config.default_source_type {
-                    SourceType::RqNyaa => tasks.push(
-                        async move {
-                            let episode = episode_query.await?;
-                            log::trace!("nyaa searched for direct episode, options: {episode:#?}");
-                            let selected_item = match episode.into_iter().next() {
-                                Some(v) => Some(v),
-                                None => {
-                                    let batch = batch_query.await?;
-                                    log::trace!(
-                                        "nyaa fell back to batch searching, options: {batch:#?}"
-                                    );
-                                    batch.into_iter().next()
-                                }
+                                Ok::<_, eyre::Report>(Message::MakePlayable(
+                                    req,
+                                    playable_fut.await?,
+                                ))
                             }
-                            .ok_or_eyre("nyaa failed to select a source")?;
-
-                            log::trace!("nyaa selected item {selected_item:?}");
-                            let playable_fut =
-                                Box::into_pin(NoDebug::into_inner(selected_item.media));
-
-                            Ok::<_, eyre::Report>(Message::MakePlayable(req, playable_fut.await?))
-                        }
-                        .into_task(),
-                    ),
+                            .into_task(),
+                        )
+                    }
                 }
             }
             Message::Play(req, play) => {
@@ -702,9 +877,96 @@ impl Monsoon {
                 );
             }
             Message::Shift(s) => self.live.shift_held = s,
+            Message::Library(m) => match m {
+                ModifyLibrary::Refresh => self.refresh_library(&mut tasks),
+                ModifyLibrary::Downloaded(show_id, media) => {
+                    log::info!(
+                        "downloaded episode {} of show {show_id:?} to {}",
+                        media.episode,
+                        media.path.display()
+                    );
+                    self.live.library.finished();
+                    let identifier = media.identifier();
+                    let dir = media.dir.clone();
+                    let added = self.db.shows.update_with(show_id, |v| {
+                        // replaces downloads whose files went missing
+                        v.media_cache.retain(
+                            |m| !matches!(m, AnyMedia::Local(l) if l.episode == media.episode),
+                        );
+                        v.media_cache.push(AnyMedia::Local(media));
+                    });
+                    match added {
+                        Some(()) => {
+                            if let Some(dedupe) = self.live.show_source_dedupe.get_mut(&show_id) {
+                                dedupe.insert(identifier);
+                            }
+                        }
+                        // removed while downloading
+                        None => tasks.push(delete_download(dir)),
+                    }
+                    self.start_download(&mut tasks);
+                }
+                ModifyLibrary::DownloadFailed(show_id, episode, e) => {
+                    log::warn!("downloading episode {episode} of show {show_id:?} failed: {e:?}");
+                    self.live.library.finished();
+                    self.start_download(&mut tasks);
+                }
+            },
         }
         tasks.batch()
     }
+    /// Delete expired downloads, then queue and start downloads as configured
+    fn refresh_library(&mut self, tasks: &mut TaskList) {
+        let playing = self
+            .live
+            .current_player_session
+            .as_ref()
+            .and_then(|v| v.playing.as_ref())
+            .map(|v| (v.show, v.episode_idx));
+        let expired = library::expired(
+            &self.config.library,
+            self.db.shows.enumerate(),
+            EpochInstant::now(),
+        );
+        for (show_id, media) in expired {
+            if playing == Some((show_id, media.episode)) {
+                continue;
+            }
+            let _ = self.db.shows.update_with(show_id, |v| {
+                v.media_cache
+                    .retain(|m| !matches!(m, AnyMedia::Local(l) if l.path == media.path));
+            });
+            if let Some(dedupe) = self.live.show_source_dedupe.get_mut(&show_id) {
+                dedupe.remove(&media.identifier());
+            }
+            tasks.push(delete_download(media.dir));
+        }
+        if self.config.library.enabled {
+            self.live
+                .library
+                .plan(&self.config.library, self.db.shows.enumerate());
+            self.start_download(tasks);
+        }
+    }
+    fn start_download(&mut self, tasks: &mut TaskList) {
+        let Some(job) = self
+            .live
+            .library
+            .next_job(&self.config.library, &self.db.shows)
+        else {
+            return;
+        };
+        let (show, episode) = (job.show, job.episode);
+        tasks.push(Task::perform(
+            job.run(self.live.get_rqstream()),
+            move |res| match res {
+                Ok(media) => Message::Library(ModifyLibrary::Downloaded(show, media)),
+                Err(e) => {
+                    Message::Library(ModifyLibrary::DownloadFailed(show, episode, Arc::new(e)))
+                }
+            },
+        ));
+    }
     pub fn cleanup_show(&mut self) -> Option<Task<Message>> {
         #[cfg(feature = "discord")]
         {
@@ -721,17 +983,30 @@ impl Monsoon {
                     ty: show::WatchEventType::Closed(Some(sess.player_pos)),
                 }),
             ))
-            .chain(
-                Task::future(async move {
-                    // TODO pause until memory budget or something. Keep medias in a paused state up to a certain configurable memory budget for quick reentry
-                    if let Some(mut s) = to_stop.media.lifecycle {
-                        s.update(media::MediaLifecycle::Destroy).await
-                    } else {
-                        Ok(None)
-                    }
-                })
-                .discard(),
-            ),
+            .chain(match to_stop.media.lifecycle {
+                Some(handle) => self.park_media(handle, to_stop.media.file_size),
+                None => Task::none(),
+            }),
+        )
+    }
+    /// Pause media that stopped playing, destroying whatever no longer fits the pause pool
+    fn park_media(&mut self, handle: LiveMediaHandle, size: Option<u64>) -> Task<Message> {
+        let mut pause = handle.clone();
+        let pool = &mut self.live.media_pool;
+        let evicted = pool.park(handle, size, self.config.player.pause_pool_bytes);
+        let parked = pool.contains(pause.identifier());
+        let pause = Task::future(async move {
+            if parked {
+                pause.update(media::MediaLifecycle::Pause).await
+            } else {
+                Ok(None)
+            }
+        });
+        Task::batch(
+            evicted
+                .into_iter()
+                .map(destroy_media)
+                .chain([pause.discard()]),
         )
     }
     pub fn quit_player_session(&mut self) -> Option<iced_runtime::Task<Message>> {
@@ -794,6 +1069,11 @@ impl Monsoon {
                 every(Duration::from_secs(1)).map(|_| Message::Session(ModifySession::PollPos)),
             );
         }
+        // downloads expire and new episodes get watched even while nothing happens here
+        #[cfg(not(test))]
+        subs.push(
+            every(Duration::from_secs(10 * 60)).map(|_| Message::Library(ModifyLibrary::Refresh)),
+        );
 
         Subscription::batch(subs)
     }
@@ -835,6 +1115,7 @@ pub enum Message {
     RequestPlay(PlayRequest),
     MakePlayable(PlayRequest, AnyMedia),
     Play(PlayRequest, PlayableMedia),
+    Library(ModifyLibrary),
 }
 #[derive(Debug, Clone)]
 pub enum Watch {
@@ -858,6 +1139,16 @@ pub enum ModifyShow {
     RequestRemove,
     ShowMoreInfo,
     SetNumEpisodes(Option<NonZeroU32>),
+    /// Override the configured translation preference for this show, or go back to it with `None`
+    SetTranslation(Option<TranslationPreference>),
+}
+
+#[derive(Debug, Clone)]
+pub enum ModifyLibrary {
+    /// apply retention and the quota, then queue and start downloads
+    Refresh,
+    Downloaded(ShowId, LocalMedia),
+    DownloadFailed(ShowId, u32, Arc<eyre::Report>),
 }
 
 #[derive(Debug, Clone)]
@@ -871,6 +1162,20 @@ pub enum AddAnime {
     CreateFromAnilist(Box<anilist_moe::models::Anime>),
 }
 
+/// Stop media for good, in the background
+fn destroy_media(mut handle: LiveMediaHandle) -> Task<Message> {
+    Task::future(async move { handle.update(media::MediaLifecycle::Destroy).await }).discard()
+}
+/// Remove a download's directory from the library, in the background
+fn delete_download(dir: PathBuf) -> Task<Message> {
+    Task::future(async move {
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            log::warn!("failed to delete download {}: {e}", dir.display());
+        }
+    })
+    .discard()
+}
+
 async fn load_image_url(url: String) -> eyre::Result<image::Handle> {
     let resp = reqwest::get(url)
         .await
@@ -0,0 +1,359 @@
+//! Episodes downloaded ahead of time, so they play (again) without downloading anything
+use std::{cmp::Reverse, collections::VecDeque, path::PathBuf, sync::Arc};
+
+use eyre::Context;
+use rqstream::{ResultExt, Rqstream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::TypedTree,
+    media::{
+        AnyMedia, Media,
+        local::LocalMedia,
+        torrent::{TorrentMedia, resolve_torrent_url},
+    },
+    show::{EpochInstant, Show, ShowId},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// download mode, keeping the next [`Config::episodes_ahead`] unwatched episodes of shows in progress on disk
+    pub enabled: bool,
+    /// where downloads go, `library` in the data directory if unset
+    pub dir: Option<PathBuf>,
+    pub episodes_ahead: u32,
+    /// nothing more is downloaded once downloads take up this many bytes
+    pub quota_bytes: Option<u64>,
+    /// delete downloads this many days after their episode was marked watched
+    pub keep_watched_days: Option<u32>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            episodes_ahead: 2,
+            quota_bytes: None,
+            keep_watched_days: Some(7),
+        }
+    }
+}
+
+/// Queued and running downloads, one at a time
+pub struct Library {
+    dir: PathBuf,
+    queue: VecDeque<(ShowId, u32)>,
+    active: Option<(ShowId, u32)>,
+}
+
+/// A download that was started, see [`Library::next_job`]
+pub(crate) struct Job {
+    pub show: ShowId,
+    pub episode: u32,
+    torrent: TorrentMedia,
+    file_id: usize,
+    dir: PathBuf,
+    max_len: Option<u64>,
+}
+
+impl Library {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            queue: VecDeque::new(),
+            active: None,
+        }
+    }
+
+    /// Queue the next [`Config::episodes_ahead`] unwatched episodes of every show in progress, most recently watched
+    /// show first. Only episodes with a cached torrent source can be downloaded
+    pub(crate) fn plan<'a>(
+        &mut self,
+        config: &Config,
+        shows: impl IntoIterator<Item = (ShowId, &'a Show)>,
+    ) {
+        let mut in_progress: Vec<_> = shows
+            .into_iter()
+            .filter_map(|(id, show)| Some((*show.watch_history.keys().next_back()?, id, show)))
+            .collect();
+        in_progress.sort_by_key(|v| Reverse(v.0));
+        for (_, id, show) in in_progress {
+            let Some((next, _)) = show.next_episode() else {
+                continue;
+            };
+            let end = next
+                .saturating_add(config.episodes_ahead)
+                .min(show.watched_episodes.len() as u32);
+            for ep in next..end {
+                let job = (id, ep);
+                if self.active == Some(job) || self.queue.contains(&job) || !wanted(show, ep) {
+                    continue;
+                }
+                self.queue.push_back(job);
+            }
+        }
+    }
+
+    /// Start the next queued download that is still wanted, unless one is running or the quota is used up
+    pub(crate) fn next_job(
+        &mut self,
+        config: &Config,
+        shows: &TypedTree<ShowId, Show>,
+    ) -> Option<Job> {
+        if self.active.is_some() {
+            return None;
+        }
+        let max_len = config
+            .quota_bytes
+            .map(|quota| quota.saturating_sub(used_bytes(shows.enumerate())));
+        if max_len == Some(0) {
+            return None;
+        }
+        while let Some((id, ep)) = self.queue.pop_front() {
+            let Some(show) = shows.get(id) else {
+                continue;
+            };
+            if !wanted(show, ep) {
+                continue;
+            }
+            let Some((torrent, file_id)) = torrent_for(show, ep) else {
+                continue;
+            };
+            self.active = Some((id, ep));
+            return Some(Job {
+                show: id,
+                episode: ep,
+                torrent: torrent.clone(),
+                file_id: file_id as usize,
+                dir: self
+                    .dir
+                    .join(u64::from(id).to_string())
+                    .join(format!("E{:02}", ep + 1)),
+                max_len,
+            });
+        }
+        None
+    }
+
+    /// The running download is done, successfully or not
+    pub(crate) fn finished(&mut self) {
+        self.active = None;
+    }
+}
+
+impl Job {
+    pub(crate) async fn run(
+        self,
+        rq: impl Future<Output = eyre::Result<Arc<Rqstream>>>,
+    ) -> eyre::Result<LocalMedia> {
+        let rq = rq.await?;
+        let torrent = resolve_torrent_url(&self.torrent.magnet_or_torrent_file_url).await?;
+        let path = rq
+            .download(torrent, self.file_id, self.dir.clone(), self.max_len)
+            .await
+            .anyhow_to_eyre()?;
+        let size = tokio::fs::metadata(&path)
+            .await
+            .wrap_err("reading download size")?
+            .len();
+        Ok(LocalMedia {
+            episode: self.episode,
+            path,
+            dir: self.dir,
+            size,
+            origin: self.torrent.identifier(),
+            watched_at: None,
+        })
+    }
+}
+
+/// Downloads to delete: watched ones past [`Config::keep_watched_days`], then the longest watched ones until the
+/// rest fits [`Config::quota_bytes`]. Unwatched episodes are never deleted
+pub(crate) fn expired<'a>(
+    config: &Config,
+    shows: impl IntoIterator<Item = (ShowId, &'a Show)>,
+    now: EpochInstant,
+) -> Vec<(ShowId, LocalMedia)> {
+    let mut used = 0;
+    let mut watched = Vec::new();
+    for (id, show) in shows {
+        for media in downloads(show) {
+            used += media.size;
+            if let Some(at) = media.watched_at {
+                watched.push((at, id, media));
+            }
+        }
+    }
+    watched.sort_by_key(|v| v.0);
+    let keep_secs = config
+        .keep_watched_days
+        .map(|days| u64::from(days) * 24 * 60 * 60);
+
+    let mut expired = Vec::new();
+    for (at, id, media) in watched {
+        let too_old = keep_secs.is_some_and(|keep| now.secs().saturating_sub(at.secs()) >= keep);
+        let over_quota = config.quota_bytes.is_some_and(|quota| used > quota);
+        // oldest first, so neither holds for the rest either
+        if !too_old && !over_quota {
+            break;
+        }
+        used -= media.size;
+        expired.push((id, media.clone()));
+    }
+    expired
+}
+
+pub(crate) fn downloads(show: &Show) -> impl Iterator<Item = &LocalMedia> {
+    show.media_cache.iter().filter_map(|v| match v {
+        AnyMedia::Local(v) => Some(v),
+        _ => None,
+    })
+}
+
+fn used_bytes<'a>(shows: impl IntoIterator<Item = (ShowId, &'a Show)>) -> u64 {
+    shows
+        .into_iter()
+        .flat_map(|(_, show)| downloads(show))
+        .map(|v| v.size)
+        .sum()
+}
+
+/// Whether `ep` is unwatched, not downloaded yet and can be downloaded
+fn wanted(show: &Show, ep: u32) -> bool {
+    show.watched_episodes
+        .get(ep as usize)
+        .is_some_and(|watched| !watched)
+        && !downloads(show).any(|v| v.has_ep(ep))
+        && torrent_for(show, ep).is_some()
+}
+
+fn torrent_for(show: &Show, ep: u32) -> Option<(&TorrentMedia, u32)> {
+    show.media_cache.iter().find_map(|v| match v {
+        AnyMedia::Torrent(v) => Some((v, *v.files_for_episode_idx.get(&ep)?)),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroU32,
+        path::PathBuf,
+        sync::{Arc, atomic::AtomicU32},
+    };
+
+    use super::{Config, Library, expired};
+    use crate::{
+        media::{
+            AnyMedia,
+            local::LocalMedia,
+            torrent::{TorrentMedia, TorrentMeta},
+        },
+        show::{EpochInstant, Show, ShowId, WatchEvent, WatchEventType},
+    };
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn show(watched: &[bool], last_watched: u64) -> Show {
+        let mut show = Show {
+            num_episodes: NonZeroU32::new(watched.len() as u32),
+            watched_episodes: watched.to_vec(),
+            ..Default::default()
+        };
+        show.watch_history.insert(
+            EpochInstant::from_secs(last_watched),
+            WatchEvent {
+                episode: 0,
+                ty: WatchEventType::Opened,
+            },
+        );
+        show.media_cache.push(AnyMedia::Torrent(TorrentMedia {
+            files_for_episode_idx: (0..watched.len() as u32).map(|v| (v, v)).collect(),
+            magnet_or_torrent_file_url: "magnet:?xt=urn:btih:batch".into(),
+            meta: Arc::new(TorrentMeta {
+                title: "batch".into(),
+                magnet_source: None,
+                seeders: AtomicU32::new(0),
+                leechers: AtomicU32::new(0),
+            }),
+        }));
+        show
+    }
+
+    fn download(episode: u32, size: u64, watched_at: Option<u64>) -> AnyMedia {
+        AnyMedia::Local(LocalMedia {
+            episode,
+            path: PathBuf::from(format!("/library/E{episode:02}/ep.mkv")),
+            dir: PathBuf::from(format!("/library/E{episode:02}")),
+            size,
+            origin: "magnet:?xt=urn:btih:batch".into(),
+            watched_at: watched_at.map(EpochInstant::from_secs),
+        })
+    }
+
+    #[test]
+    fn plan() {
+        let config = Config::default();
+        let a = show(&[true, false, false, false], 10);
+        let b = show(&[true, true, false], 20);
+        let mut unstarted = show(&[false, false], 0);
+        unstarted.watch_history.clear();
+        let mut untorrented = show(&[true, false], 30);
+        untorrented.media_cache.clear();
+        let shows = [
+            (ShowId::from(1), &a),
+            (ShowId::from(2), &b),
+            (ShowId::from(3), &unstarted),
+            (ShowId::from(4), &untorrented),
+        ];
+
+        let mut library = Library::new(PathBuf::from("/library"));
+        library.plan(&config, shows);
+        // most recently watched show first, and only episodes it can get
+        assert_eq!(
+            Vec::from(library.queue.clone()),
+            [
+                (ShowId::from(2), 2),
+                (ShowId::from(1), 1),
+                (ShowId::from(1), 2)
+            ]
+        );
+        // planning again queues nothing twice
+        library.plan(&config, shows);
+        assert_eq!(library.queue.len(), 3);
+    }
+
+    #[test]
+    fn retention_and_quota() {
+        let now = 100 * DAY;
+        let mut s = show(&[true, true, true, false], 0);
+        s.media_cache.extend([
+            download(0, 10, Some(now - 8 * DAY)),
+            download(1, 10, Some(now - 2 * DAY)),
+            download(2, 10, Some(now - DAY)),
+            download(3, 10, None),
+        ]);
+        let shows = [(ShowId::from(1), &s)];
+        let episodes = |config: &Config| {
+            expired(config, shows, EpochInstant::from_secs(now))
+                .into_iter()
+                .map(|(_, v)| v.episode)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(episodes(&Config::default()), [0]);
+        let quota = Config {
+            quota_bytes: Some(25),
+            ..Default::default()
+        };
+        assert_eq!(episodes(&quota), [0, 1]);
+        // never the unwatched one, even if that is still too much
+        let tiny = Config {
+            quota_bytes: Some(5),
+            keep_watched_days: None,
+            ..Default::default()
+        };
+        assert_eq!(episodes(&tiny), [0, 1, 2]);
+    }
+}
@@ -5,18 +5,27 @@ use bincode::{Decode, Encode};
 use crate::{
     LiveState,
     media::{
+        allanime::AllAnimeMedia,
+        local::LocalMedia,
         torrent::{TorrentMedia, TorrentMeta},
         url::{UrlMedia, UrlMeta},
     },
     show::ShowId,
 };
 
+pub mod allanime;
+pub mod local;
+pub mod pool;
 pub mod torrent;
 pub mod url;
 
 #[derive(Clone, Debug)]
 pub enum Playable {
-    Url(String),
+    Url {
+        url: String,
+        /// sent with every request for `url`
+        headers: url::Headers,
+    },
     File(PathBuf),
 }
 #[derive(Clone, Debug)]
@@ -37,11 +46,16 @@ pub enum MediaLifecycle {
 
 #[derive(Clone, Debug)]
 pub struct LiveMediaHandle {
+    /// [`Media::identifier`] of the media this was played from
+    identifier: Arc<str>,
     send: tokio::sync::watch::Sender<MediaLifecycle>,
     recv_err: tokio::sync::watch::Receiver<Option<Arc<eyre::Report>>>,
 }
 
 impl LiveMediaHandle {
+    pub(crate) fn identifier(&self) -> &Arc<str> {
+        &self.identifier
+    }
     pub(crate) async fn update(
         &mut self,
         msg: MediaLifecycle,
@@ -56,6 +70,8 @@ impl LiveMediaHandle {
 pub enum SourceMeta {
     Torrent(Arc<TorrentMeta>),
     Url(Arc<UrlMeta>),
+    /// downloaded into the library
+    Local,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -94,4 +110,6 @@ pub trait Media {
 pub enum AnyMedia {
     Torrent(TorrentMedia),
     Url(UrlMedia),
+    Local(LocalMedia),
+    AllAnime(AllAnimeMedia),
 }
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use bincode::{Decode, Encode};
+use eyre::OptionExt;
+
+use crate::{
+    LiveState,
+    media::{Media, PlayRequest, Playable, PlayableMedia, SourceMeta, url::UrlMeta},
+    source::{
+        Translation,
+        allanime::{select_link, translation_type},
+    },
+};
+
+/// An episode of a show on AllAnime. Its stream links expire, so they are resolved again every time it is played
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct AllAnimeMedia {
+    pub episode: u32,
+    /// AllAnime's ID of the show
+    pub show_id: Arc<str>,
+    pub show_name: Arc<str>,
+    /// AllAnime's name of the episode, e.g. "1"
+    pub episode_name: Arc<str>,
+    pub translation: Translation,
+    /// prefer streams of at most this vertical resolution, if there is a choice
+    pub preferred_resolution: Option<u32>,
+}
+impl Media for AllAnimeMedia {
+    fn has_ep(&self, idx: u32) -> bool {
+        self.episode == idx
+    }
+
+    fn play(
+        &self,
+        for_show: &PlayRequest,
+        live: &mut LiveState,
+    ) -> Option<Box<dyn Future<Output = eyre::Result<PlayableMedia>> + Send + 'static>> {
+        if for_show.episode_idx != self.episode {
+            return None;
+        }
+        let client = live.allanime.clone();
+        let this = self.clone();
+
+        Some(Box::new(async move {
+            let translation = translation_type(this.translation)
+                .ok_or_eyre("allanime has no such translation")?;
+            let sources = client
+                .sources(&this.show_id, &this.episode_name, translation)
+                .await?;
+            let links = client.resolve_all(&sources).await;
+            let link = select_link(links, this.preferred_resolution).ok_or_else(|| {
+                eyre::eyre!(
+                    "no playable allanime stream for episode {}",
+                    this.episode_name
+                )
+            })?;
+            let file_name = format!("{} - {:0>2}", this.show_name, this.episode_name);
+            let meta = Arc::new(UrlMeta {
+                source_name: format!("AllAnime ({:?})", link.hoster).into(),
+                file_name: file_name.as_str().into(),
+                resolution: link.resolution.map(|v| format!("{v}p").into()),
+            });
+            Ok(PlayableMedia {
+                playable: Playable::Url {
+                    url: link.url.into(),
+                    headers: link
+                        .headers
+                        .into_iter()
+                        .map(|(k, v)| (k.into(), v.into()))
+                        .collect(),
+                },
+                file_name: Some(file_name),
+                file_size: None,
+                lifecycle: None,
+                meta: SourceMeta::Url(meta),
+            })
+        }))
+    }
+    fn identifier(&self) -> Arc<str> {
+        format!(
+            "allanime:{}:{}:{}",
+            self.show_id, self.translation, self.episode_name
+        )
+        .into()
+    }
+}
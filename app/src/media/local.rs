@@ -0,0 +1,60 @@
+use std::{path::PathBuf, sync::Arc};
+
+use bincode::{Decode, Encode};
+
+use crate::{
+    LiveState,
+    media::{Media, PlayRequest, Playable, PlayableMedia, SourceMeta},
+    show::EpochInstant,
+};
+
+/// An episode downloaded into the library, see [`crate::library`]
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct LocalMedia {
+    pub episode: u32,
+    pub path: PathBuf,
+    /// library directory `path` was downloaded into, deleted along with it
+    pub dir: PathBuf,
+    pub size: u64,
+    /// [`Media::identifier`] of what it was downloaded from
+    pub origin: Arc<str>,
+    /// when the episode was marked watched, which retention counts from
+    pub watched_at: Option<EpochInstant>,
+}
+
+impl Media for LocalMedia {
+    fn has_ep(&self, idx: u32) -> bool {
+        // files deleted behind our back fall through to other sources
+        self.episode == idx && self.path.is_file()
+    }
+
+    fn play(
+        &self,
+        for_show: &PlayRequest,
+        _live: &mut LiveState,
+    ) -> Option<Box<dyn Future<Output = eyre::Result<PlayableMedia>> + Send + 'static>> {
+        if for_show.episode_idx != self.episode {
+            return None;
+        }
+        let playable = Playable::File(self.path.clone());
+        let file_name = self
+            .path
+            .file_name()
+            .map(|v| v.to_string_lossy().into_owned());
+        let file_size = Some(self.size);
+
+        Some(Box::new(async move {
+            Ok(PlayableMedia {
+                playable,
+                file_name,
+                file_size,
+                lifecycle: None,
+                meta: SourceMeta::Local,
+            })
+        }))
+    }
+
+    fn identifier(&self) -> Arc<str> {
+        self.path.to_string_lossy().into()
+    }
+}
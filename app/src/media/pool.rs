@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use crate::media::LiveMediaHandle;
+
+/// Media that stopped playing, kept paused so playing it (or more of the same torrent) again starts warm.
+///
+/// Media is counted by its file size, as that is the most a paused torrent can be holding on to for it
+#[derive(Default, Debug)]
+pub struct MediaPool {
+    /// least recently stopped first
+    entries: VecDeque<Pooled>,
+}
+
+#[derive(Debug)]
+struct Pooled {
+    size: u64,
+    handle: LiveMediaHandle,
+}
+
+impl MediaPool {
+    /// Keep `handle` of media `size` bytes large, returning the handles to destroy: an older one of the same media,
+    /// and the least recently stopped ones until everything fits into `budget` bytes. `handle` itself is returned if
+    /// it can never fit, so a budget of 0 keeps nothing
+    #[must_use]
+    pub(crate) fn park(
+        &mut self,
+        handle: LiveMediaHandle,
+        size: Option<u64>,
+        budget: u64,
+    ) -> Vec<LiveMediaHandle> {
+        let mut evicted: Vec<_> = self.take(handle.identifier()).into_iter().collect();
+        // unknown sizes could be holding anything
+        let Some(size) = size.filter(|v| budget > 0 && *v <= budget) else {
+            evicted.push(handle);
+            return evicted;
+        };
+        let mut used: u64 = self.entries.iter().map(|v| v.size).sum();
+        while used + size > budget
+            && let Some(oldest) = self.entries.pop_front()
+        {
+            used -= oldest.size;
+            evicted.push(oldest.handle);
+        }
+        self.entries.push_back(Pooled { size, handle });
+        evicted
+    }
+
+    /// Take back the handle of the media `identifier`, e.g. because it is being played again and a new stream
+    /// already keeps it warm
+    pub(crate) fn take(&mut self, identifier: &str) -> Option<LiveMediaHandle> {
+        let idx = self
+            .entries
+            .iter()
+            .position(|v| **v.handle.identifier() == *identifier)?;
+        self.entries.remove(idx).map(|v| v.handle)
+    }
+
+    /// Take back everything, least recently stopped first
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = LiveMediaHandle> {
+        self.entries.drain(..).map(|v| v.handle)
+    }
+
+    pub(crate) fn contains(&self, identifier: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|v| **v.handle.identifier() == *identifier)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::MediaPool;
+    use crate::media::{LiveMediaHandle, MediaLifecycle};
+
+    fn handle(identifier: &str) -> LiveMediaHandle {
+        let (send, _) = tokio::sync::watch::channel(MediaLifecycle::Resume);
+        let (_, recv_err) = tokio::sync::watch::channel(None);
+        LiveMediaHandle {
+            identifier: Arc::from(identifier),
+            send,
+            recv_err,
+        }
+    }
+
+    fn identifiers(handles: impl IntoIterator<Item = LiveMediaHandle>) -> Vec<String> {
+        handles
+            .into_iter()
+            .map(|v| v.identifier().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_stopped() {
+        let mut pool = MediaPool::default();
+        assert!(pool.park(handle("a"), Some(40), 100).is_empty());
+        assert!(pool.park(handle("b"), Some(40), 100).is_empty());
+        assert_eq!(identifiers(pool.park(handle("c"), Some(40), 100)), ["a"]);
+        // stopping the same media again replaces the older handle, moving it to the back
+        assert_eq!(identifiers(pool.park(handle("b"), Some(40), 100)), ["b"]);
+        assert_eq!(identifiers(pool.park(handle("d"), Some(60), 100)), ["c"]);
+        assert_eq!(identifiers(pool.drain()), ["b", "d"]);
+    }
+
+    #[test]
+    fn never_fits() {
+        let mut pool = MediaPool::default();
+        assert!(pool.park(handle("a"), Some(40), 100).is_empty());
+        assert_eq!(
+            identifiers(pool.park(handle("a"), Some(200), 100)),
+            ["a", "a"]
+        );
+        assert_eq!(identifiers(pool.park(handle("b"), None, 100)), ["b"]);
+        assert_eq!(identifiers(pool.park(handle("c"), Some(10), 0)), ["c"]);
+        assert!(pool.is_empty());
+
+        assert!(pool.park(handle("d"), Some(10), 100).is_empty());
+        assert!(pool.contains("d"));
+        assert_eq!(identifiers(pool.take("d")), ["d"]);
+        assert!(pool.take("d").is_none());
+    }
+}
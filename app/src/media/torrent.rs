@@ -4,7 +4,7 @@ use std::{
 };
 
 use bincode::{Decode, Encode};
-use eyre::Context;
+use eyre::{Context, OptionExt};
 use librqbit::AddTorrent;
 use rqstream::ResultExt;
 
@@ -72,24 +72,37 @@ impl Media for TorrentMedia {
         Some(Box::new(async move {
             let rq = get_rqstream.await?;
             let torrent = resolve_torrent_url(&mag).await?;
-            let torrent = rq.add_managed(torrent).await.anyhow_to_eyre()?;
-            torrent.wait_until_initialized().await.anyhow_to_eyre()?;
+            // only needed until the stream is up, which keeps the torrent around by itself
+            let lease = rq.add_managed(torrent).await.anyhow_to_eyre()?;
+            lease.wait_until_initialized().await.anyhow_to_eyre()?;
 
             let show: u64 = show.into();
             let (send_lifecycle, mut recv_lifecycle) =
                 tokio::sync::watch::channel(MediaLifecycle::Resume);
-            let rq_meta = torrent.metadata.load();
-            let file_extension = rq_meta
+            let file_extension = lease
+                .metadata
+                .load()
                 .as_ref()
                 .and_then(|v| v.file_infos[f as usize].relative_filename.extension())
-                .and_then(|v| v.to_str().map(|s| format!(".{s}")))
+                .and_then(|v| v.to_str())
+                // routes have to be URL safe
+                .filter(|v| v.chars().all(|c| c.is_ascii_alphanumeric()))
+                .map(|v| format!(".{v}"))
                 .unwrap_or(String::new());
             let episode_number = episode_idx + 1;
 
             let subpath = format!("{show}_E{episode_number:02}{file_extension}");
-            let path = format!("http://127.0.0.1:9000/stream/{subpath}");
-            let mut stream = None;
-            let lc_torrent = torrent.clone();
+            let stream = rq
+                .stream_file(&lease, f as usize, subpath)
+                .await
+                .anyhow_to_eyre()
+                .wrap_err("starting stream")?;
+            // the route may have been renamed if another stream already uses it
+            let path = rq
+                .stream_url(stream)
+                .await
+                .ok_or_eyre("stream stopped before playback")?;
+            let lc_torrent = Arc::clone(lease.torrent());
 
             tokio::spawn(async move {
                 while recv_lifecycle.changed().await.is_ok() {
@@ -98,29 +111,17 @@ impl Media for TorrentMedia {
                     let res = match msg {
                         MediaLifecycle::Pause => {
                             log::trace!("torrent lifecycle pause");
-                            rq.session.pause(&lc_torrent).await.anyhow_to_eyre()
+                            // other streams of the same batch may still be downloading
+                            rq.pause(&lc_torrent).await.map(drop).anyhow_to_eyre()
                         }
-                        MediaLifecycle::Resume => if stream.is_none() {
+                        MediaLifecycle::Resume => {
                             log::trace!("torrent lifecycle resume");
-                            match rq
-                                .stream_file(&lc_torrent, f as usize, subpath.clone())
-                                .await
-                                .anyhow_to_eyre()
-                            {
-                                Ok(v) => {
-                                    stream = Some(v);
-                                    Ok(())
-                                }
-                                Err(v) => Err(v).wrap_err("starting stream"),
+                            if lc_torrent.is_paused() {
+                                rq.session.unpause(&lc_torrent).await.anyhow_to_eyre()
+                            } else {
+                                Ok(())
                             }
-                        } else {
-                            Ok(())
                         }
-                        .and(if lc_torrent.is_paused() {
-                            rq.session.unpause(&lc_torrent).await.anyhow_to_eyre()
-                        } else {
-                            Ok(())
-                        }),
                         MediaLifecycle::Destroy => {
                             log::trace!("torrent lifecycle destroy");
                             should_break = true;
@@ -132,15 +133,13 @@ impl Media for TorrentMedia {
                         || send_error.send(res.err().map(Arc::new)).is_err()
                         || should_break
                     {
-                        if let Some(id) = stream {
-                            let _ = rq.stop_streaming(id).await;
-                        }
+                        let _ = rq.stop_streaming(stream).await;
                         // goobye worlw ;u;
                         break;
                     }
                 }
             });
-            let info = torrent.metadata.load();
+            let info = lease.metadata.load();
             let (file_name, file_size) = info
                 .as_ref()
                 .and_then(|v| v.file_infos.get(f as usize))
@@ -151,10 +150,14 @@ impl Media for TorrentMedia {
                     )
                 });
             Ok(PlayableMedia {
-                playable: Playable::Url(path),
+                playable: Playable::Url {
+                    url: path,
+                    headers: Vec::new(),
+                },
                 file_name,
                 file_size,
                 lifecycle: Some(LiveMediaHandle {
+                    identifier: mag,
                     send: send_lifecycle,
                     recv_err,
                 }),
@@ -7,11 +7,16 @@ use crate::{
     media::{Media, PlayRequest, PlayableMedia},
 };
 
+/// HTTP request headers as (name, value) pairs
+pub type Headers = Vec<(Arc<str>, Arc<str>)>;
+
 /// a single, externally hosted URL of an episode
 #[derive(Debug, Clone, Encode, Decode)]
 pub struct UrlMedia {
     pub episode: u32,
     pub url: Arc<str>,
+    /// headers the host requires to serve `url`, e.g. a Referer or User-Agent
+    pub headers: Headers,
     pub meta: Arc<UrlMeta>,
 }
 impl Media for UrlMedia {
@@ -29,7 +34,10 @@ impl Media for UrlMedia {
         if episode_idx != self.episode {
             return None;
         };
-        let playable = crate::media::Playable::Url(self.url.to_string());
+        let playable = crate::media::Playable::Url {
+            url: self.url.to_string(),
+            headers: self.headers.clone(),
+        };
         let file_name = Some(self.meta.file_name.to_string());
         let meta = crate::media::SourceMeta::Url(self.meta.clone());
 
@@ -4,6 +4,7 @@ use std::{env::temp_dir, fs::File, io::Write, sync::Arc, time::Duration};
 
 use eyre::OptionExt;
 use mpv_ipc::{MpvIpc, MpvSpawnOptions};
+use serde_json::{Map, Value, json};
 use tokio::sync::{Mutex, watch::Receiver};
 
 use crate::{FAILED_LOAD_IMAGE, media::PlayingMedia};
@@ -16,6 +17,26 @@ pub struct PlayerSession {
     pub player_remaining: u32,
 }
 
+/// Per-file mpv options that make it send `headers` with its requests
+fn header_options(headers: &[(Arc<str>, Arc<str>)]) -> Map<String, Value> {
+    let mut options = Map::new();
+    let mut fields = Vec::new();
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("referer") {
+            options.insert("referrer".into(), (**value).into());
+        } else if name.eq_ignore_ascii_case("user-agent") {
+            options.insert("user-agent".into(), (**value).into());
+        } else {
+            // http-header-fields is a comma separated list
+            fields.push(format!("{name}: {value}").replace(',', "\\,"));
+        }
+    }
+    if !fields.is_empty() {
+        options.insert("http-header-fields".into(), fields.join(",").into());
+    }
+    options
+}
+
 #[derive(Debug)]
 pub struct PlayerSessionMpv {
     mpv: MpvIpc,
@@ -91,9 +112,24 @@ impl PlayerSessionMpv {
         })
     }
 
-    pub(crate) async fn play(&mut self, url: String) -> eyre::Result<()> {
+    pub(crate) async fn play(
+        &mut self,
+        url: String,
+        headers: &[(Arc<str>, Arc<str>)],
+    ) -> eyre::Result<()> {
         self.ensure_started().await?;
-        self.mpv.send_command(["loadfile", &*url].into()).await?;
+        let command = if headers.is_empty() {
+            ["loadfile", &*url].into()
+        } else {
+            // named arguments, so the options do not depend on the positional `index` argument newer mpv versions added
+            json!({
+                "name": "loadfile",
+                "url": &*url,
+                "flags": "replace",
+                "options": header_options(headers),
+            })
+        };
+        self.mpv.send_command(command).await?;
         // wait for file to be set
         self.recv_path
             .wait_for(move |v| matches!(v, serde_json::Value::String(path) if path == &url))
@@ -134,3 +170,32 @@ impl PlayerSessionMpv {
             .unwrap_or(u32::MAX as f64) as u32
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::header_options;
+
+    #[test]
+    fn header_options_split() {
+        let h = |k: &str, v: &str| (Arc::<str>::from(k), Arc::<str>::from(v));
+        let options = header_options(&[
+            h("Referer", "https://allmanga.to"),
+            h("user-agent", "Mozilla/5.0 (KHTML, like Gecko)"),
+            h("Cookie", "a=1"),
+            h("Accept", "video/mp4,*/*"),
+        ]);
+        assert_eq!(
+            serde_json::Value::Object(options),
+            json!({
+                "referrer": "https://allmanga.to",
+                "user-agent": "Mozilla/5.0 (KHTML, like Gecko)",
+                "http-header-fields": "Cookie: a=1,Accept: video/mp4\\,*/*",
+            })
+        );
+        assert!(header_options(&[]).is_empty());
+    }
+}
@@ -8,7 +8,7 @@ use bincode::{Decode, Encode};
 use chrono::{DateTime, Local, TimeZone};
 use derive_more::{From, Into};
 
-use crate::{Config, NameKind, media::AnyMedia};
+use crate::{Config, NameKind, media::AnyMedia, source::TranslationPreference};
 
 /// persistent unique identifier for a show in the database
 #[derive(Debug, Clone, Copy, PartialEq, Eq, From, Into, Hash, Encode, Decode)]
@@ -30,6 +30,10 @@ impl EpochInstant {
     pub(crate) fn secs(self) -> u64 {
         self.0
     }
+    #[cfg(test)]
+    pub(crate) fn from_secs(secs: u64) -> Self {
+        Self(secs, 0)
+    }
 
     pub(crate) fn to_local_dt(self) -> DateTime<Local> {
         Local
@@ -55,6 +59,14 @@ pub struct Show {
     pub media_cache: Vec<AnyMedia>,
     pub relations: Relations,
 }
+
+/// Per-show overrides of [`Config`] settings.
+///
+/// Kept in its own tree rather than on [`Show`], so adding settings does not change the encoding of existing shows
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct ShowPrefs {
+    pub translation: Option<TranslationPreference>,
+}
 #[derive(Debug, Clone, Encode, Decode)]
 pub enum MediaSource {
     Magnet(String),
@@ -70,6 +82,23 @@ impl Show {
             .map(|v| &*v.1)
             .unwrap_or("")
     }
+    /// Mark episode `ep` watched or unwatched, returns `false` if there is no such episode
+    pub(crate) fn set_watched(&mut self, ep: u32, watched: bool) -> bool {
+        let Some(v) = self.watched_episodes.get_mut(ep as usize) else {
+            return false;
+        };
+        *v = watched;
+        // retention of downloads counts from here
+        let at = watched.then(EpochInstant::now);
+        for media in &mut self.media_cache {
+            if let AnyMedia::Local(local) = media
+                && local.episode == ep
+            {
+                local.watched_at = at;
+            }
+        }
+        true
+    }
     pub fn next_episode(&self) -> Option<(u32, Option<u32>)> {
         let mut ep = self.num_episodes.map(NonZero::get).unwrap_or(1) - 1;
         if self.watched_episodes.len() != (ep + 1) as usize {
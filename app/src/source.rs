@@ -1,9 +1,13 @@
+use std::fmt::Display;
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
 use crate::{LiveState, media::AnyMedia, show::Show, util::NoDebug};
 
 pub mod allanime;
 pub mod nyaa;
 
-
 #[derive(Debug)]
 pub struct QueryItem {
     pub source: SourceKind,
@@ -15,16 +19,72 @@ pub struct QueryItem {
 
 pub trait Source {
     /// Perform a search for the given show and optionally episode.
+    ///
+    /// Items in a translation not listed in `translation` are left out, the rest are ordered by it before anything else.
     fn query(
         &self,
         live: &mut LiveState,
         config: &crate::Config,
         show: &Show,
+        translation: &TranslationPreference,
         filter_episode: Option<u32>,
     ) -> impl Future<Output = eyre::Result<Vec<QueryItem>>> + Send + 'static;
 }
 #[derive(Debug)]
 pub enum SourceKind {
     Nyaa,
+    AllAnime,
     File,
 }
+
+/// The audio and subtitles of a release
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum Translation {
+    /// original audio with english subtitles
+    Sub,
+    /// english audio
+    Dub,
+    /// both original and english audio
+    DualAudio,
+    /// original audio without subtitles
+    Raw,
+}
+impl Translation {
+    pub const ALL: [Self; 4] = [Self::Sub, Self::Dub, Self::DualAudio, Self::Raw];
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Translation::Sub => "sub",
+            Translation::Dub => "dub",
+            Translation::DualAudio => "dual audio",
+            Translation::Raw => "raw",
+        }
+    }
+}
+impl Display for Translation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Acceptable translations, most preferred first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(transparent)]
+pub struct TranslationPreference(pub Vec<Translation>);
+impl Default for TranslationPreference {
+    fn default() -> Self {
+        Self(vec![Translation::Sub])
+    }
+}
+impl TranslationPreference {
+    /// Position of `translation` in the preference, or `None` if it is not acceptable at all
+    pub fn rank(&self, translation: Translation) -> Option<usize> {
+        self.0.iter().position(|&v| v == translation)
+    }
+    pub fn contains(&self, translation: Translation) -> bool {
+        self.rank(translation).is_some()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Translation> + '_ {
+        self.0.iter().copied()
+    }
+}
@@ -1,9 +1,121 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    num::NonZero,
+    sync::Arc,
+};
+
+use ::allanime::{AllAnimeClient, AllAnimeShow, StreamLink, TranslationType};
+use eyre::OptionExt;
+use iced_runtime::futures::futures::future::join_all;
+use log::trace;
+use serde::{Deserialize, Serialize};
+
 use crate::{
     LiveState,
+    media::{AnyMedia, allanime::AllAnimeMedia},
     show::Show,
-    source::{QueryItem, Source},
+    source::{QueryItem, Source, SourceKind, Translation, TranslationPreference},
 };
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// ignore AllAnime shows whose names are less similar than this to every name of the show (0.0 to 1.0)
+    pub(crate) min_name_similarity: f64,
+    /// prefer streams of at most this vertical resolution when selecting, if there is a choice
+    pub(crate) preferred_resolution: Option<u32>,
+    /// client config
+    pub(crate) allanime: ::allanime::AllAnimeClientConfig,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_name_similarity: 0.6,
+            preferred_resolution: Some(1080),
+            allanime: Default::default(),
+        }
+    }
+}
+
+/// Lowercased alphanumeric words, so punctuation and spacing differences between sites do not matter
+fn normalize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_lowercase().next().unwrap_or(c)
+            } else {
+                ' '
+            }
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Sørensen–Dice coefficient of the character bigrams of the normalized names, from 0.0 (nothing in common) to 1.0 (equal)
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| {
+        let chars: Vec<_> = normalize(s).chars().collect();
+        chars
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .collect::<BTreeSet<_>>()
+    };
+    let (a, b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    2.0 * a.intersection(&b).count() as f64 / (a.len() + b.len()) as f64
+}
+
+/// Scores how likely `candidate` is to be `show`, or `None` if its names are too different
+fn score_candidate(
+    show_names: &[&str],
+    num_episodes: Option<u32>,
+    candidate: &AllAnimeShow,
+    min_similarity: f64,
+) -> Option<f64> {
+    let similarity = candidate
+        .names()
+        .flat_map(|c| show_names.iter().map(move |s| name_similarity(s, c)))
+        .fold(0.0, f64::max);
+    if similarity < min_similarity {
+        return None;
+    }
+    // seasons and sequels usually share most of their name, so the episode count is the tiebreaker
+    let episodes = match (num_episodes, candidate.episode_count) {
+        (Some(a), Some(b)) if a == b => 0.2,
+        (Some(_), Some(_)) => -0.2,
+        _ => 0.0,
+    };
+    Some(similarity + episodes)
+}
+
+/// Picks the stream to play, the best ranked one within the preferred resolution if there is one
+pub(crate) fn select_link(
+    links: Vec<StreamLink>,
+    preferred_resolution: Option<u32>,
+) -> Option<StreamLink> {
+    let within = |l: &StreamLink| {
+        preferred_resolution.is_none_or(|max| l.resolution.is_some_and(|r| r <= max))
+    };
+    match links.iter().position(within) {
+        Some(idx) => links.into_iter().nth(idx),
+        None => links.into_iter().next(),
+    }
+}
+
+/// AllAnime has no dual audio listings, dubs are listed separately from the subbed original
+pub(crate) fn translation_type(translation: Translation) -> Option<TranslationType> {
+    match translation {
+        Translation::Sub => Some(TranslationType::Sub),
+        Translation::Dub => Some(TranslationType::Dub),
+        Translation::Raw => Some(TranslationType::Raw),
+        Translation::DualAudio => None,
+    }
+}
+
 pub struct AllAnime;
 impl Source for AllAnime {
     fn query(
@@ -11,9 +123,214 @@ impl Source for AllAnime {
         live: &mut LiveState,
         config: &crate::Config,
         show: &Show,
+        translation: &TranslationPreference,
         filter_episode: Option<u32>,
     ) -> impl Future<Output = eyre::Result<Vec<QueryItem>>> + Send + 'static {
-        let n = show.get_preferred_name(config).to_string().into_boxed_str();
-        async move { todo!() }
+        let client = live.allanime.clone();
+        let conf = config.allanime.clone();
+        let names: Vec<String> = show
+            .names
+            .iter()
+            .map(|(_, name)| name.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let num_episodes = show.num_episodes.map(NonZero::get);
+
+        let translations: Vec<_> = translation
+            .iter()
+            .filter(|v| translation_type(*v).is_some())
+            .collect();
+
+        async move {
+            if translations.is_empty() {
+                eyre::bail!("allanime has none of the preferred translations");
+            }
+            let mut last_err = None;
+            // fall back to the next translation only if the show has nothing for this one
+            for translation in translations {
+                match query_translation(
+                    &client,
+                    &conf,
+                    &names,
+                    num_episodes,
+                    translation,
+                    filter_episode,
+                )
+                .await
+                {
+                    Ok(items) if !items.is_empty() => return Ok(items),
+                    Ok(_) => trace!("allanime has no {translation} episodes"),
+                    Err(e) => {
+                        log::warn!("allanime {translation} query failed: {e:?}");
+                        last_err = Some(e);
+                    }
+                }
+            }
+            match last_err {
+                Some(e) => Err(e),
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+}
+
+async fn query_translation(
+    client: &AllAnimeClient,
+    conf: &Config,
+    names: &[String],
+    num_episodes: Option<u32>,
+    translation: Translation,
+    filter_episode: Option<u32>,
+) -> eyre::Result<Vec<QueryItem>> {
+    let ty = translation_type(translation).ok_or_eyre("allanime has no such translation")?;
+    // search by the ascii names, the API does not match native names well
+    let searched: Vec<_> = names.iter().filter(|v| v.is_ascii()).collect();
+    let mut candidates = HashMap::new();
+    let mut last_err = None;
+    let mut any_succeeded = false;
+    for (name, resp) in searched
+        .iter()
+        .zip(join_all(searched.iter().map(|name| client.search(name, ty))).await)
+    {
+        match resp {
+            Ok(shows) => {
+                any_succeeded = true;
+                candidates.extend(shows.into_iter().map(|v| (v.id.clone(), v)));
+            }
+            Err(e) => {
+                log::warn!("allanime search for {name:?} failed: {e:?}");
+                last_err = Some(e);
+            }
+        }
+    }
+    // a single failed search is fine as long as another name found something
+    if !any_succeeded && let Some(e) = last_err {
+        return Err(e);
+    }
+
+    let show_names: Vec<_> = names.iter().map(String::as_str).collect();
+    let (_, matched) = candidates
+        .into_values()
+        .filter_map(|c| {
+            let score = score_candidate(&show_names, num_episodes, &c, conf.min_name_similarity);
+            trace!("allanime candidate {} scored {score:?}", c.name);
+            Some((score?, c))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .ok_or_eyre("no matching show on allanime")?;
+    log::info!("matched allanime show {} ({})", matched.name, matched.id);
+
+    let episodes = client.episodes(&matched.id).await?;
+    let show_id: Arc<str> = matched.id.into();
+    let show_name: Arc<str> = matched.name.into();
+    Ok(episodes
+        .get(ty)
+        .iter()
+        // allanime numbers episodes from 1, fractional episodes (recaps etc) have no index
+        .filter_map(|ep| Some((ep.parse::<u32>().ok()?.checked_sub(1)?, ep.clone())))
+        .filter(|(idx, _)| filter_episode.is_none_or(|f| f == *idx))
+        .map(|(idx, ep)| {
+            // stream links expire, so only the episode is kept and they are resolved once it is played
+            let media = AnyMedia::AllAnime(AllAnimeMedia {
+                episode: idx,
+                show_id: show_id.clone(),
+                show_name: show_name.clone(),
+                episode_name: ep.into(),
+                translation,
+                preferred_resolution: conf.preferred_resolution,
+            });
+            let media: Box<dyn Future<Output = eyre::Result<AnyMedia>> + Send + 'static> =
+                Box::new(async move { Ok(media) });
+            QueryItem {
+                source: SourceKind::AllAnime,
+                name: format!("{show_name} - Episode {} ({translation})", idx + 1).into(),
+                file_size: None,
+                media: media.into(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use ::allanime::{AllAnimeShow, AllanimeHosterName, AvailableEpisodes, StreamKind, StreamLink};
+
+    use super::{name_similarity, score_candidate, select_link};
+
+    fn show(name: &str, english: Option<&str>, episode_count: Option<u32>) -> AllAnimeShow {
+        AllAnimeShow {
+            id: name.into(),
+            name: name.into(),
+            english_name: english.map(Into::into),
+            native_name: None,
+            alt_names: Vec::new(),
+            thumbnail: None,
+            episode_count,
+            available_episodes: AvailableEpisodes::default(),
+        }
+    }
+
+    fn link(resolution: Option<u32>) -> StreamLink {
+        StreamLink {
+            url: format!("https://example.com/{resolution:?}").into(),
+            hoster: AllanimeHosterName::Default,
+            kind: StreamKind::File,
+            resolution,
+            headers: Default::default(),
+            subtitles: Vec::new(),
+            priority: 0.0,
+        }
+    }
+
+    #[test]
+    fn similarity() {
+        assert_eq!(name_similarity("Bloom Into You", "bloom into you!"), 1.0);
+        assert!(name_similarity("Yagate Kimi ni Naru", "Yagate Kimi ni Naru Specials") > 0.75);
+        assert!(name_similarity("Bloom Into You", "Sound! Euphonium") < 0.3);
+        assert_eq!(name_similarity("", "anything"), 0.0);
+    }
+
+    #[test]
+    fn episode_count_breaks_ties() {
+        let names = ["Kaguya-sama: Love is War"];
+        let s1 = show(
+            "Kaguya-sama wa Kokurasetai",
+            Some("Kaguya-sama: Love is War"),
+            Some(12),
+        );
+        let movie = show(
+            "Kaguya-sama wa Kokurasetai: First Kiss wa Owaranai",
+            Some("Kaguya-sama: Love is War -The First Kiss That Never Ends-"),
+            Some(1),
+        );
+        let unrelated = show(
+            "Sousou no Frieren",
+            Some("Frieren: Beyond Journey's End"),
+            Some(28),
+        );
+
+        let score = |c| score_candidate(&names, Some(12), c, 0.6);
+        assert!(score(&s1).unwrap() > score(&movie).unwrap_or(0.0));
+        assert_eq!(score(&unrelated), None);
+    }
+
+    #[test]
+    fn prefers_resolution() {
+        let links = vec![link(Some(2160)), link(Some(1080)), link(None)];
+        assert_eq!(
+            select_link(links.clone(), Some(1080)).unwrap().resolution,
+            Some(1080)
+        );
+        assert_eq!(
+            select_link(links.clone(), None).unwrap().resolution,
+            Some(2160)
+        );
+        // nothing within the preference, take the best there is
+        assert_eq!(
+            select_link(links, Some(480)).unwrap().resolution,
+            Some(2160)
+        );
+        assert!(select_link(Vec::new(), None).is_none());
     }
 }
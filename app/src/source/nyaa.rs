@@ -7,16 +7,17 @@ use std::{
 use anitomy::ElementObject;
 use iced_runtime::futures::futures::future::join_all;
 use log::trace;
-use nyaa::Item;
+use nyaa::{AnimeKind, Item, MediaCategory};
 use rqstream::ResultExt;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    LiveState,
     media::{
         AnyMedia,
         torrent::{TorrentMedia, TorrentMeta, resolve_torrent_url},
     },
-    source::{QueryItem, Source, SourceKind},
+    source::{QueryItem, Source, SourceKind, Translation, TranslationPreference},
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +42,40 @@ impl Default for Config {
     }
 }
 
+/// Guesses the translation of a release from its category and the usual title tags
+fn classify(category: &MediaCategory, title: &str) -> Translation {
+    if matches!(category, MediaCategory::Anime(Some(AnimeKind::Raw))) {
+        return Translation::Raw;
+    }
+    let title = title.to_lowercase();
+    let words: Vec<_> = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|v| !v.is_empty())
+        .collect();
+    let has_pair = |a, b| words.windows(2).any(|w| w[0] == a && w[1] == b);
+    if has_pair("dual", "audio") || has_pair("multi", "audio") || words.contains(&"dualaudio") {
+        Translation::DualAudio
+    } else if words
+        .iter()
+        .any(|v| matches!(*v, "dub" | "dubs" | "dubbed" | "engdub"))
+    {
+        Translation::Dub
+    } else {
+        Translation::Sub
+    }
+}
+
+/// The narrowest category that still contains every acceptable translation
+fn category_for(translation: &TranslationPreference) -> MediaCategory {
+    let raw = translation.contains(Translation::Raw);
+    let translated = translation.iter().any(|v| v != Translation::Raw);
+    MediaCategory::Anime(match (raw, translated) {
+        (true, false) => Some(AnimeKind::Raw),
+        (true, true) => None,
+        (false, _) => Some(AnimeKind::SubEnglish),
+    })
+}
+
 pub struct Nyaa;
 impl Source for Nyaa {
     fn query(
@@ -48,6 +83,7 @@ impl Source for Nyaa {
         live: &mut crate::LiveState,
         config: &crate::Config,
         show: &crate::show::Show,
+        translation: &TranslationPreference,
         filter_episode: Option<u32>,
     ) -> impl Future<Output = eyre::Result<Vec<QueryItem>>> + Send + 'static {
         pub struct QueryWithMeta {
@@ -56,21 +92,22 @@ impl Source for Nyaa {
             used_name: String,
             query: ::nyaa::SearchQuery,
         }
-        fn meta(name: String, (ep, season, query): (bool, bool, String)) -> QueryWithMeta {
+        let category = category_for(translation);
+        let meta = |name: String, (ep, season, query): (bool, bool, String)| -> QueryWithMeta {
             QueryWithMeta {
                 _has_episode: ep,
                 has_season: season,
                 used_name: name,
                 query: ::nyaa::SearchQuery {
                     query,
-                    category: nyaa::MediaCategory::Anime(Some(nyaa::AnimeKind::SubEnglish)),
+                    category,
                     filter: nyaa::Filter::NoFilter,
                     max_page_idx: NonZeroUsize::new(5),
                     sort: Default::default(),
                     user: None,
                 },
             }
-        }
+        };
 
         let guess = show.season_number_guess();
         let make_query = |name: &String| {
@@ -90,9 +127,8 @@ impl Source for Nyaa {
                 name.split_whitespace()
                     .filter(|v| match s {
                         true => {
-                            dbg!(v);
                             s = false;
-                            !dbg!(v.contains(char::is_numeric))
+                            !v.contains(char::is_numeric)
                         }
                         false => {
                             s = v.contains("season") || v.contains("Season");
@@ -151,28 +187,37 @@ impl Source for Nyaa {
             conf.preferred_size *= 10;
         }
 
+        let translation = translation.clone();
         let nyaa = live.nyaa.clone();
-        let rq = live.get_rqstream();
+        // only start the torrent session once a candidate is actually resolved
+        let rq = Arc::clone(&live.rqstream);
 
         async move {
-            let rq = rq.await?;
             let mut all_items = BTreeMap::new();
             // basic heuristic for scoring responses based on a combination of desired size and seeder/leecher counts
             let score_item = |it: &Item| {
-                let s = *it.size.as_ref().unwrap() as i64;
+                let s = it.size as i64;
                 if s == 0 {
                     return i64::MAX;
                 }
+                // prefer trusted uploads and avoid remakes where there is a choice
+                let status = match (it.is_trusted, it.is_remake) {
+                    (_, true) => 100,
+                    (true, false) => -100,
+                    (false, false) => 0,
+                };
                 // negative if below the preferred size, positive if past it
                 // normalize to percentage above/below preferred size
-                ((s - conf.preferred_size as i64) / (s / 10)) - it.seeders as i64 * 15
+                ((s - conf.preferred_size as i64) / (s / 10).max(1)) - it.seeders as i64 * 15
                     + it.leechers as i64 / 10
+                    + status
             };
             let mk_media =
                 |it: Item| -> Box<dyn Future<Output = eyre::Result<AnyMedia>> + Send + 'static> {
-                    let rq = rq.clone();
+                    let rq = Arc::clone(&rq);
                     Box::new(async move {
-                        println!("attempting media");
+                        trace!("making media for {}", it.title);
+                        let rq = LiveState::init_rqstream(rq).await?;
                         let torrent_parsed = ElementObject::from_iter(anitomy::parse(&it.title));
 
                         let info = rq
@@ -206,7 +251,6 @@ impl Source for Nyaa {
                                 files_for_episode_idx.insert(ep - 1, idx as u32);
                             }
                         }
-                        println!("made media");
                         Ok(TorrentMedia {
                             files_for_episode_idx,
                             magnet_or_torrent_file_url: it.magnet_link.into(),
@@ -224,7 +268,8 @@ impl Source for Nyaa {
                 };
             for resp in
                 join_all(queries.iter().map(|query| async {
-                    let results = nyaa.search(dbg!(&query.query)).await?;
+                    trace!("searching nyaa for {:?}", query.query);
+                    let results = nyaa.search(&query.query).await?;
 
                     Ok::<_, eyre::Report>(results.results.into_iter().filter_map(|v| {
                         let parsed = ElementObject::from_iter(anitomy::parse(&v.title));
@@ -236,11 +281,7 @@ impl Source for Nyaa {
                             return None;
                         }
 
-                        if v.size
-                            .as_ref()
-                            .ok()
-                            .is_none_or(|&size| size > conf.max_size)
-                        {
+                        if v.size > conf.max_size {
                             trace!("rejected source {name}: too large (max_size: {})", conf.max_size);
                             return None;
                         }
@@ -254,16 +295,17 @@ impl Source for Nyaa {
                             trace!("rejected source {name}: single episode in batch mode (anitomy: {parsed:#?})");
                             return None;
                         }
-                        if v.title.contains("dub") | v.title.contains("Dub"){
-                            trace!("rejected source {name}: Dub");
+                        let kind = classify(&v.category, name);
+                        let Some(rank) = translation.rank(kind) else {
+                            trace!("rejected source {name}: unwanted translation {kind}");
                             return None;
-                        }
+                        };
                         Some((
-                            score_item(&v),
+                            (rank, score_item(&v)),
                             QueryItem {
                                 source: SourceKind::Nyaa,
                                 name: v.title.clone(),
-                                file_size: v.size.as_ref().ok().copied(),
+                                file_size: Some(v.size),
                                 media: mk_media(v).into(),
                             },
                         ))
@@ -275,8 +317,129 @@ impl Source for Nyaa {
             {
                 all_items.extend(resp?);
             }
-            println!("completed query");
             Ok(all_items.into_values().collect())
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use directories::ProjectDirs;
+    use nyaa::{
+        AnimeKind, MediaCategory,
+        mock::{MockNyaa, fixtures},
+    };
+
+    use crate::{
+        Config, LiveState, NameKind,
+        show::Show,
+        source::{Source, SourceKind, Translation, TranslationPreference},
+    };
+
+    use super::{Nyaa, category_for, classify};
+
+    #[test]
+    fn classify_titles() {
+        let sub = MediaCategory::Anime(Some(AnimeKind::SubEnglish));
+        let raw = MediaCategory::Anime(Some(AnimeKind::Raw));
+        let cases = [
+            (
+                "[Erai-raws] Yagate Kimi ni Naru - 10 [720p].mkv",
+                Translation::Sub,
+            ),
+            (
+                "[Judas] Bloom Into You (Season 1) [BD 1080p][HEVC x265 10bit][Dual-Audio]",
+                Translation::DualAudio,
+            ),
+            (
+                "Bloom Into You S01 1080p BluRay DUAL AUDIO",
+                Translation::DualAudio,
+            ),
+            (
+                "[SubsPlease] Bloom Into You (English Dub) - 10 (1080p)",
+                Translation::Dub,
+            ),
+            ("[Anime Time] Bloom Into You [Dubbed]", Translation::Dub),
+            // "dub" inside another word is not a tag
+            ("[Dubu] Yagate Kimi ni Naru - 10", Translation::Sub),
+        ];
+        for (title, expected) in cases {
+            assert_eq!(classify(&sub, title), expected, "{title}");
+        }
+        assert_eq!(
+            classify(&raw, "[Ohys-Raws] Yagate Kimi ni Naru - 10"),
+            Translation::Raw
+        );
+    }
+
+    #[test]
+    fn category_covers_preference() {
+        let pref = |v: &[Translation]| TranslationPreference(v.to_vec());
+        let cat = |v| match category_for(&pref(v)) {
+            MediaCategory::Anime(kind) => kind,
+            _ => unreachable!(),
+        };
+        assert!(matches!(
+            cat(&[Translation::Sub]),
+            Some(AnimeKind::SubEnglish)
+        ));
+        assert!(matches!(
+            cat(&[Translation::Dub, Translation::DualAudio]),
+            Some(AnimeKind::SubEnglish)
+        ));
+        assert!(matches!(cat(&[Translation::Raw]), Some(AnimeKind::Raw)));
+        assert!(cat(&[Translation::Sub, Translation::Raw]).is_none());
+    }
+
+    #[tokio::test]
+    async fn query_offline() {
+        let server = MockNyaa::new()
+            .fallback_listing([fixtures::LISTING])
+            .serve()
+            .await
+            .unwrap();
+        let mut config = Config::default();
+        config.nyaa.nyaa = server.client_config();
+        // an absolute path roots every directory in it, and nothing is written there anyway
+        let dirs = ProjectDirs::from_path(std::env::temp_dir().join("monsoon-test")).unwrap();
+        let mut live = LiveState::new(&config, &dirs);
+        let show = Show {
+            names: [(NameKind::English, "Yagate Kimi ni Naru".to_string())].into(),
+            num_episodes: NonZeroU32::new(13),
+            watched_episodes: vec![false; 13],
+            ..Default::default()
+        };
+
+        let items = Nyaa
+            .query(&mut live, &config, &show, &config.translation, Some(9))
+            .await
+            .unwrap();
+        // the 1080p release is over the default max_size, the rest are ordered by score
+        let names: Vec<_> = items.iter().map(|v| &*v.name).collect();
+        assert_eq!(
+            names,
+            [
+                "[Erai-raws] Yagate Kimi ni Naru - 10 [720p].mkv",
+                "[Moozzi2] Yagate Kimi ni Naru - 10 (BD 1920x1080 x.264 Flac)",
+            ]
+        );
+        assert!(items.iter().all(|v| matches!(v.source, SourceKind::Nyaa)));
+        assert_eq!(items[0].file_size, Some(300 * 1024 * 1024));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("S01E10"), "{requests:?}");
+        // candidates are resolved lazily, so no torrent session was started
+        assert!(live.rqstream.get().is_none());
+
+        // none of the listed releases are dubbed
+        let dub = TranslationPreference(vec![Translation::Dub]);
+        let items = Nyaa
+            .query(&mut live, &config, &show, &dub, Some(9))
+            .await
+            .unwrap();
+        assert!(items.is_empty());
+    }
+}
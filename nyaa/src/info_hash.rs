@@ -0,0 +1,86 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::NyaaError;
+
+/// A torrent's v1 info hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InfoHash(pub [u8; 20]);
+
+impl FromStr for InfoHash {
+    type Err = NyaaError;
+
+    /// Parses the 40 character hex representation, or the 32 character base32 representation some magnet links use
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || NyaaError::InvalidInfoHash(s.into());
+        if s.len() == 32 {
+            return base32_decode(s).map(Self).ok_or_else(err);
+        }
+        if s.len() != 40 || !s.is_ascii() {
+            return Err(err());
+        }
+        let mut out = [0; 20];
+        for (byte, pair) in out.iter_mut().zip(s.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(str::from_utf8(pair).map_err(|_| err())?, 16)
+                .map_err(|_| err())?;
+        }
+        Ok(Self(out))
+    }
+}
+
+impl Display for InfoHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl InfoHash {
+    /// Extracts the info hash from the `xt=urn:btih:` parameter of a magnet link
+    pub fn from_magnet(magnet: &str) -> Option<Self> {
+        magnet
+            .strip_prefix("magnet:?")?
+            .split('&')
+            .find_map(|param| param.strip_prefix("xt=urn:btih:"))?
+            .parse()
+            .ok()
+    }
+}
+
+fn base32_decode(s: &str) -> Option<[u8; 20]> {
+    let mut out = [0; 20];
+    let (mut buf, mut bits, mut idx) = (0u64, 0, 0);
+    for c in s.bytes() {
+        let val = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buf = (buf << 5) | val as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            *out.get_mut(idx)? = (buf >> bits) as u8;
+            idx += 1;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::InfoHash;
+
+    #[test]
+    fn info_hash_from_magnet() {
+        let hex = InfoHash::from_magnet(
+            "magnet:?xt=urn:btih:0123456789ABCDEF0123456789abcdef01234567&dn=test&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce",
+        )
+        .unwrap();
+        assert_eq!(hex.to_string(), "0123456789abcdef0123456789abcdef01234567");
+        let base32 =
+            InfoHash::from_magnet("magnet:?dn=test&xt=urn:btih:AERUKZ4JVPG66AJDIVTYTK6N54ASGRLH")
+                .unwrap();
+        assert_eq!(hex, base32);
+        assert!(InfoHash::from_magnet("magnet:?xt=urn:btih:0123").is_none());
+        assert!(InfoHash::from_magnet("https://nyaa.si/download/1.torrent").is_none());
+    }
+}
@@ -1,22 +1,50 @@
 use std::{
     num::{NonZero, NonZeroUsize},
     str::FromStr,
-    sync::OnceLock,
+    sync::Arc,
     time::Duration,
 };
 
-use futures::{FutureExt, future::join_all};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt, future::join_all, stream};
 use reqwest::Url;
-use scraper::{ElementRef, Html, Selector};
 use serde::{Deserialize, Serialize};
 use size::Size;
 use strum::{AsRefStr, EnumDiscriminants, FromRepr, IntoDiscriminant};
 use thiserror::Error;
 
+mod info_hash;
+mod limit;
+mod listing;
+#[cfg(feature = "test-support")]
+pub mod mock;
+mod rss;
+mod view;
+
+pub use info_hash::InfoHash;
+pub use view::{FileEntry, TorrentDetails};
+
 #[derive(Debug, Error)]
 pub enum NyaaError {
     #[error("failed to request nyaa page")]
     GetError(#[from] reqwest::Error),
+    #[error("invalid nyaa instance url {url:?}")]
+    InvalidInstanceUrl {
+        url: Box<str>,
+        #[source]
+        source: url::ParseError,
+    },
+    #[error("nyaa responded with {0}")]
+    Status(reqwest::StatusCode),
+    #[error("nyaa response was not UTF-8")]
+    NonUtf8Body(#[from] std::str::Utf8Error),
+    #[error("failed to parse nyaa RSS feed")]
+    RssError(#[from] roxmltree::Error),
+    #[error("unrecognized nyaa page layout: could not find {0}")]
+    UnrecognizedLayout(&'static str),
+    #[error("invalid info hash {0:?}")]
+    InvalidInfoHash(Box<str>),
 }
 #[derive(Debug, Error)]
 pub enum ParseMediaCategoryError {
@@ -30,14 +58,28 @@ pub enum ParseMediaCategoryError {
 #[derive(Clone)]
 pub struct NyaaClient {
     pub client: reqwest::Client,
+    /// Note that the request limits are read once in [`NyaaClient::new`]; changing them afterwards has no effect
     pub config: NyaaClientConfig,
+    limiter: Arc<limit::RequestLimiter>,
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct NyaaClientConfig {
     /// URL of the nyaa instance to connect to, *including the protocol*
     pub instance_url: Box<str>,
     /// Request timeout
     pub timeout: Option<Duration>,
+    /// Which representation of the search listing [`NyaaClient::search`] reads
+    pub search_mode: SearchMode,
+    /// Maximum number of requests in flight at once, across all clones of the client. `None` for no limit
+    pub max_concurrency: Option<NonZeroUsize>,
+    /// Maximum number of requests sent per second, across all clones of the client. `None` for no limit
+    pub requests_per_second: Option<f64>,
+    /// How many times a request is retried after a 429, 5xx or connection error
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each retry after it. A `Retry-After` header from the server takes
+    /// precedence, unless it is longer than the last retry's backoff, in which case the request fails instead
+    pub retry_backoff: Duration,
 }
 
 impl Default for NyaaClientConfig {
@@ -45,167 +87,220 @@ impl Default for NyaaClientConfig {
         Self {
             instance_url: "https://nyaa.si".into(),
             timeout: None,
+            search_mode: SearchMode::default(),
+            max_concurrency: NonZeroUsize::new(4),
+            requests_per_second: Some(2.0),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(500),
         }
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SearchMode {
+    /// Scrape the HTML listing pages
+    #[default]
+    Html,
+    /// Read the `?page=rss` feed. Only ever returns the first page of results
+    Rss,
+    /// Read the RSS feed, scraping the HTML listing instead if the feed could not be read
+    RssWithHtmlFallback,
+}
+
 impl NyaaClient {
     pub fn new(config: NyaaClientConfig) -> Self {
         Self {
             client: reqwest::Client::new(),
+            limiter: Arc::new(limit::RequestLimiter::new(
+                config.max_concurrency,
+                config.requests_per_second,
+            )),
             config,
         }
     }
-    pub async fn search(&self, q: &SearchQuery) -> Result<SearchResponse, NyaaError> {
-        struct ScrapeSelectors([Selector; 11]);
-        fn make_sels() -> ScrapeSelectors {
-            fn selector(s: &str) -> Selector {
-                Selector::parse(s).expect("statically specified selector to parse")
+    fn instance_url(&self) -> Result<Url, NyaaError> {
+        Url::parse(&self.config.instance_url).map_err(|source| NyaaError::InvalidInstanceUrl {
+            url: self.config.instance_url.clone(),
+            source,
+        })
+    }
+    /// GET a page, treating non-success statuses (e.g. Cloudflare 429/503 pages) as errors.
+    /// Waits for the shared request budget, and retries with exponential backoff on 429/5xx statuses and connection errors
+    async fn fetch(&self, url: Url) -> Result<Bytes, NyaaError> {
+        let mut attempt = 0;
+        loop {
+            let (res, retry_after) = {
+                let _permit = self.limiter.acquire().await;
+                self.fetch_once(url.clone()).await
+            };
+            let retryable = match &res {
+                Ok(_) => false,
+                Err(NyaaError::Status(status)) => {
+                    *status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+                }
+                Err(NyaaError::GetError(e)) => e.is_connect() || e.is_timeout(),
+                Err(_) => false,
+            };
+            if !retryable || attempt >= self.config.max_retries {
+                return res;
             }
-            ScrapeSelectors([
-                selector("table.torrent-list > tbody > tr"),
-                selector("td:first-of-type > a"),
-                selector("td:nth-of-type(2) > a:last-of-type"),
-                selector("td:nth-of-type(3) > a:nth-of-type(2)"),
-                selector("td:nth-of-type(3) > a:nth-of-type(1)"),
-                selector("td:nth-of-type(4)"),
-                selector("td:nth-of-type(5)"),
-                selector("td:nth-of-type(6)"),
-                selector("td:nth-of-type(7)"),
-                selector("td:nth-of-type(8)"),
-                selector(".pagination-page-info"),
-            ])
+            let backoff = |n| self.config.retry_backoff * 2u32.saturating_pow(n);
+            let delay = match retry_after {
+                // waiting out a longer Retry-After would stall the caller for however long the server asks
+                Some(v) if v > backoff(self.config.max_retries) => return res,
+                Some(v) => v,
+                None => backoff(attempt),
+            };
+            log::debug!(
+                "retrying nyaa request to {url} in {delay:?}: {:?}",
+                res.err()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
-        // cache selectors
-        static SELECTORS: OnceLock<ScrapeSelectors> = OnceLock::new();
-        const MAX_PAGE_IDX: usize = 100;
-        const MAX_ITEMS_PER_PAGE: usize = 75;
-
-        let SearchQuery {
-            query,
-            category,
-            filter,
-            max_page_idx,
-            sort,
-            user,
-        } = q;
-
-        let instance_url = Url::parse(&self.config.instance_url).expect("instance url to be valid");
-        let (cat, subcat) = category.encode();
-        let (by, order) = (sort.by.as_ref(), sort.order.as_ref());
-        let user = user.as_deref().unwrap_or_default();
-        let filter = *filter as u8;
-        let query = urlencoding::encode(query);
-        let body_for_page = |page: usize| {
-            let mut u = instance_url.clone();
-            u.set_query(Some(&format!(
-                "q={query}&c={cat}_{subcat}&f={filter}&p={page}&s={by}&o={order}&u={user}",
-            )));
-            let mut builder = self.client.get(u);
-            if let Some(dur) = self.config.timeout {
-                builder = builder.timeout(dur);
-            }
-            builder.send().then(|v| async {
-                match v {
-                    Ok(resp) => Ok(resp.bytes().await?),
-                    Err(e) => Err(e),
-                }
-            })
-        };
-
-        let ScrapeSelectors(
-            [
-                selector_item,
-                selector_icon,
-                selector_title,
-                selector_magnet,
-                selector_torrent,
-                selector_size,
-                selector_date,
-                selector_seeders,
-                selector_leechers,
-                selector_downloads,
-                selector_pagination,
-            ],
-        ) = SELECTORS.get_or_init(make_sels);
-
-        let mut items = Vec::new();
-        let mut process_response = |bytes: &[u8]| -> usize {
-            let html = Html::parse_document(str::from_utf8(bytes).expect("response to be UTF-8"));
-            let num_results: usize = html
-                .select(selector_pagination)
-                .next()
-                .and_then(|v| {
-                    v.inner_html()
-                        .split(' ')
-                        .nth(5)
-                        .and_then(|v| str::parse(v).ok())
-                })
-                .unwrap_or(MAX_PAGE_IDX * MAX_ITEMS_PER_PAGE);
-
-            items.extend(html.select(selector_item).filter_map(|el| {
-                let select_attr =
-                    |sel, attr| el.select(sel).next().and_then(|v| v.value().attr(attr));
-
-                let select_inner = |sel| el.select(sel).next().as_ref().map(ElementRef::inner_html);
-
-                let size = select_inner(selector_size)?;
-                let mut torrent_file_link = String::from(&*self.config.instance_url);
-                let id_dot_torrent = select_attr(selector_torrent, "href")?;
-                torrent_file_link.push_str(id_dot_torrent);
-
-                Some(Item {
-                    category: select_attr(selector_icon, "href")?
-                        .split('=')
-                        .next_back()?
-                        .parse()
-                        .ok()?,
-                    torrent_file_link,
-                    size: size
-                        .parse()
-                        .map(|v: Size| v.bytes().abs_diff(0))
-                        .map_err(move |_| size),
-                    title: select_attr(selector_title, "title")?.into(),
-                    magnet_link: select_attr(selector_magnet, "href")?.into(),
-                    date: select_inner(selector_date)?,
-                    seeders: select_inner(selector_seeders)?.parse().unwrap_or_default(),
-                    leechers: select_inner(selector_leechers)?.parse().unwrap_or_default(),
-                    downloads: select_inner(selector_downloads)?
-                        .parse()
-                        .unwrap_or_default(),
-                    nyaa_id: id_dot_torrent
-                        .trim_end_matches(".torrent")
-                        .trim_start_matches("/download/")
-                        .parse()
-                        .ok()?,
-                })
-            }));
-
-            num_results
+    }
+    /// A single request attempt, along with the server's `Retry-After` if it sent one
+    async fn fetch_once(&self, url: Url) -> (Result<Bytes, NyaaError>, Option<Duration>) {
+        let mut builder = self.client.get(url);
+        if let Some(dur) = self.config.timeout {
+            builder = builder.timeout(dur);
+        }
+        let resp = match builder.send().await {
+            Ok(v) => v,
+            Err(e) => return (Err(e.into()), None),
         };
+        let status = resp.status();
+        if !status.is_success() {
+            let retry_after = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok()?.trim().parse().ok())
+                .map(Duration::from_secs);
+            return (Err(NyaaError::Status(status)), retry_after);
+        }
+        (resp.bytes().await.map_err(Into::into), None)
+    }
+    /// Search using the method selected by [`NyaaClientConfig::search_mode`]
+    pub async fn search(&self, q: &SearchQuery) -> Result<SearchResponse, NyaaError> {
+        match self.config.search_mode {
+            SearchMode::Html => self.search_html(q).await,
+            SearchMode::Rss => self.search_rss(q).await,
+            SearchMode::RssWithHtmlFallback => match self.search_rss(q).await {
+                Ok(v) => Ok(v),
+                Err(e) => {
+                    log::warn!("nyaa RSS search failed, falling back to HTML: {e}");
+                    self.search_html(q).await
+                }
+            },
+        }
+    }
+    /// Search using the RSS feed. The feed is not paginated, so at most one page of results is returned.
+    pub async fn search_rss(&self, q: &SearchQuery) -> Result<SearchResponse, NyaaError> {
+        let mut u = self.instance_url()?;
+        u.set_query(Some(&format!("page=rss&{}", q.url_query())));
+        let body = self.fetch(u).await?;
 
-        let first_response = body_for_page(0).await?;
-        let num_results = process_response(&first_response);
+        Ok(SearchResponse {
+            results: rss::parse_feed(str::from_utf8(&body)?)?,
+            last_page: 1,
+            last_read_page: 1,
+        })
+    }
+    /// Fetch and parse the `/view/{id}` page of a torrent
+    pub async fn view(&self, nyaa_id: u64) -> Result<TorrentDetails, NyaaError> {
+        let u = self
+            .instance_url()?
+            .join(&format!("/view/{nyaa_id}"))
+            .map_err(|source| NyaaError::InvalidInstanceUrl {
+                url: self.config.instance_url.clone(),
+                source,
+            })?;
+        let body = self.fetch(u).await?;
+        view::parse_view(nyaa_id, &self.config.instance_url, str::from_utf8(&body)?)
+    }
+    /// Fetch and parse a single page (starting from 1) of the HTML listing
+    async fn fetch_listing(
+        &self,
+        q: &SearchQuery,
+        page: usize,
+    ) -> Result<listing::ListingPage, NyaaError> {
+        let mut u = self.instance_url()?;
+        u.set_query(Some(&format!("{}&p={page}", q.url_query())));
+        let body = self.fetch(u).await?;
+        listing::parse_listing(&self.config.instance_url, &body)
+    }
+    /// Search by scraping the HTML listing pages
+    pub async fn search_html(&self, q: &SearchQuery) -> Result<SearchResponse, NyaaError> {
+        // bail out here rather than fanning out if the first page is not understood
+        let first_page = self.fetch_listing(q, 1).await?;
+        let mut items = first_page.items;
         // max page count
-        let server_last_page = num_results.div_ceil(MAX_ITEMS_PER_PAGE);
+        let server_last_page = first_page.num_results.div_ceil(listing::MAX_ITEMS_PER_PAGE);
         // limit pages requested in unbounded case to actual max
         let last_read_page =
-            server_last_page.min(max_page_idx.map(NonZero::get).unwrap_or(usize::MAX));
+            server_last_page.min(q.max_page_idx.map(NonZero::get).unwrap_or(usize::MAX));
 
-        join_all((1..last_read_page).map(body_for_page))
-            .await
-            .into_iter()
-            .try_for_each(|v| {
-                process_response(&v?);
-                Ok::<_, NyaaError>(())
-            })?;
+        for page in join_all((2..=last_read_page).map(|page| self.fetch_listing(q, page))).await {
+            items.extend(page?.items);
+        }
         Ok(SearchResponse {
             results: items,
             last_page: server_last_page,
             last_read_page,
         })
     }
+    /// Like [`NyaaClient::search`], but lazily yields results one page at a time, in listing order.
+    /// The next page is only requested once every item of the previous one has been consumed, so dropping the stream early saves the remaining requests.
+    /// The stream ends after the first error.
+    pub fn search_stream<'a>(
+        &'a self,
+        q: &'a SearchQuery,
+    ) -> impl Stream<Item = Result<Item, NyaaError>> + 'a {
+        let max_page_idx = q.max_page_idx.map(NonZero::get).unwrap_or(usize::MAX);
+        stream::try_unfold(Some(1), move |page| async move {
+            let Some(page) = page.filter(|&p| p <= max_page_idx) else {
+                return Ok::<_, NyaaError>(None);
+            };
+            let (items, last_page) = self.search_page(q, page).await?;
+            let next = (page < last_page).then_some(page + 1);
+            Ok(Some((stream::iter(items.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+    }
+    /// Items of a single page using the configured [`SearchMode`], along with the last page number
+    async fn search_page(
+        &self,
+        q: &SearchQuery,
+        page: usize,
+    ) -> Result<(Vec<Item>, usize), NyaaError> {
+        let mode = self.config.search_mode;
+        // the feed has no further pages, so reaching page 2 in fallback mode means we are already scraping HTML
+        if mode == SearchMode::Rss || (mode == SearchMode::RssWithHtmlFallback && page == 1) {
+            match self.search_rss(q).await {
+                Ok(resp) => return Ok((resp.results, resp.last_page)),
+                Err(e) if mode == SearchMode::RssWithHtmlFallback => {
+                    log::warn!("nyaa RSS search failed, falling back to HTML: {e}");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let listing = self.fetch_listing(q, page).await?;
+        Ok((
+            listing.items,
+            listing.num_results.div_ceil(listing::MAX_ITEMS_PER_PAGE),
+        ))
+    }
+}
+
+pub(crate) fn parse_size(s: &str) -> Option<u64> {
+    s.trim()
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .parse()
+        .ok()
+        .map(|v: Size| v.bytes().abs_diff(0))
 }
+
 #[derive(Debug, Clone)]
 pub struct Item {
     pub nyaa_id: u64,
@@ -214,10 +309,17 @@ pub struct Item {
     pub downloads: u32,
     pub category: MediaCategory,
     pub magnet_link: Box<str>,
+    pub info_hash: InfoHash,
     pub torrent_file_link: String,
     pub title: Box<str>,
-    pub size: Result<u64, String>,
-    pub date: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Upload time
+    pub date: DateTime<Utc>,
+    /// Uploaded by a trusted user (green rows)
+    pub is_trusted: bool,
+    /// Marked as a remake of an existing release (red rows)
+    pub is_remake: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -235,6 +337,26 @@ pub struct SearchQuery {
     pub user: Option<String>,
 }
 
+impl SearchQuery {
+    /// URL query parameters for this search, excluding the page
+    fn url_query(&self) -> String {
+        let SearchQuery {
+            query,
+            category,
+            filter,
+            max_page_idx: _,
+            sort,
+            user,
+        } = self;
+        let (cat, subcat) = category.encode();
+        let (by, order) = (sort.by.as_ref(), sort.order.as_ref());
+        let user = user.as_deref().unwrap_or_default();
+        let filter = *filter as u8;
+        let query = urlencoding::encode(query);
+        format!("q={query}&c={cat}_{subcat}&f={filter}&s={by}&o={order}&u={user}")
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Sort {
     pub by: SortBy,
@@ -392,8 +514,9 @@ pub enum LiteratureKind {
 #[repr(u8)]
 pub enum LiveActionKind {
     TranslatedEnglish = 1,
-    TranslatedNonEnglish,
     IdolPromoVideo,
+    TranslatedNonEnglish,
+    Raw,
 }
 
 #[derive(Debug, Clone, Copy, FromRepr)]
@@ -414,19 +537,308 @@ pub enum SoftwareKind {
 mod test {
     use std::num::NonZeroUsize;
 
-    use crate::NyaaClient;
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant},
+    };
+
+    use axum::{
+        Router,
+        extract::{Query, State},
+        http::StatusCode,
+        routing::get,
+    };
+    use futures::{StreamExt, TryStreamExt};
+
+    use crate::{
+        AnimeKind, LiteratureKind, LiveActionKind, MediaCategory, NyaaClient, NyaaClientConfig,
+        NyaaError, ParseMediaCategoryError, SearchQuery, SoftwareKind,
+    };
+
+    /// Serves `router` on an ephemeral local port, returning its URL
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    fn config(instance_url: String) -> NyaaClientConfig {
+        NyaaClientConfig {
+            instance_url: instance_url.into(),
+            max_concurrency: None,
+            requests_per_second: None,
+            retry_backoff: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    /// The listing fixture, claiming `num_results` results in total
+    fn listing(num_results: usize) -> String {
+        include_str!("../fixtures/listing.html")
+            .replace("out of 3 results", &format!("out of {num_results} results"))
+    }
+
+    /// Serves 3 full pages of the listing fixture, recording which pages were requested
+    async fn paginated() -> (NyaaClient, Arc<std::sync::Mutex<Vec<usize>>>) {
+        #[derive(serde::Deserialize)]
+        struct Page {
+            p: usize,
+        }
+        let pages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route(
+                "/",
+                get(
+                    |State(pages): State<Arc<std::sync::Mutex<Vec<usize>>>>,
+                     Query(Page { p }): Query<Page>| async move {
+                        pages.lock().unwrap().push(p);
+                        axum::response::Html(listing(3 * 75))
+                    },
+                ),
+            )
+            .with_state(pages.clone());
+        (NyaaClient::new(config(serve(router).await)), pages)
+    }
+
+    #[derive(Default)]
+    struct Counters {
+        hits: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[test]
+    fn media_category_round_trip() {
+        for (code, cat) in [
+            ("0_0", MediaCategory::All),
+            ("1_0", MediaCategory::Anime(None)),
+            ("1_4", MediaCategory::Anime(Some(AnimeKind::Raw))),
+            ("3_3", MediaCategory::Literature(Some(LiteratureKind::Raw))),
+            (
+                "4_2",
+                MediaCategory::LiveAction(Some(LiveActionKind::IdolPromoVideo)),
+            ),
+            ("4_4", MediaCategory::LiveAction(Some(LiveActionKind::Raw))),
+            ("6_2", MediaCategory::Software(Some(SoftwareKind::Game))),
+        ] {
+            let decoded: MediaCategory = code.parse().unwrap();
+            assert_eq!(decoded.encode(), cat.encode(), "{code}");
+            let (c, s) = cat.encode();
+            assert_eq!(format!("{c}_{s}"), code);
+        }
+    }
+
+    #[test]
+    fn media_category_rejects_unknown() {
+        assert!(matches!(
+            MediaCategory::decode([7, 0]),
+            Err(ParseMediaCategoryError::InvalidCategory(7))
+        ));
+        assert!(matches!(
+            MediaCategory::decode([2, 3]),
+            Err(ParseMediaCategoryError::InvalidSubCategory(
+                MediaCategory::Audio(None),
+                3
+            ))
+        ));
+        for s in ["", "1", "1_x", "one_two"] {
+            assert!(
+                matches!(
+                    s.parse::<MediaCategory>(),
+                    Err(ParseMediaCategoryError::InvalidString(_))
+                ),
+                "{s:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_on_rate_limit() {
+        let counters = Arc::new(Counters::default());
+        let router = Router::new()
+            .route(
+                "/",
+                get(|State(c): State<Arc<Counters>>| async move {
+                    // rate limit the first two attempts
+                    if c.hits.fetch_add(1, Ordering::SeqCst) < 2 {
+                        Err(StatusCode::TOO_MANY_REQUESTS)
+                    } else {
+                        Ok(axum::response::Html(listing(3)))
+                    }
+                }),
+            )
+            .with_state(counters.clone());
+        let client = NyaaClient::new(config(serve(router).await));
+        let resp = client.search(&SearchQuery::default()).await.unwrap();
+        assert_eq!(resp.results.len(), 3);
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn give_up_after_max_retries() {
+        let counters = Arc::new(Counters::default());
+        let router = Router::new()
+            .route(
+                "/",
+                get(|State(c): State<Arc<Counters>>| async move {
+                    c.hits.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::SERVICE_UNAVAILABLE
+                }),
+            )
+            .with_state(counters.clone());
+        let client = NyaaClient::new(NyaaClientConfig {
+            max_retries: 2,
+            ..config(serve(router).await)
+        });
+        assert!(matches!(
+            client.search(&SearchQuery::default()).await,
+            Err(NyaaError::Status(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn no_retry_on_long_retry_after() {
+        let counters = Arc::new(Counters::default());
+        let router = Router::new()
+            .route(
+                "/",
+                get(|State(c): State<Arc<Counters>>| async move {
+                    c.hits.fetch_add(1, Ordering::SeqCst);
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        [(axum::http::header::RETRY_AFTER, "3600")],
+                    )
+                }),
+            )
+            .with_state(counters.clone());
+        let client = NyaaClient::new(config(serve(router).await));
+        assert!(matches!(
+            client.search(&SearchQuery::default()).await,
+            Err(NyaaError::Status(StatusCode::TOO_MANY_REQUESTS))
+        ));
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn no_retry_on_client_error() {
+        let counters = Arc::new(Counters::default());
+        let router = Router::new()
+            .route(
+                "/",
+                get(|State(c): State<Arc<Counters>>| async move {
+                    c.hits.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::NOT_FOUND
+                }),
+            )
+            .with_state(counters.clone());
+        let client = NyaaClient::new(config(serve(router).await));
+        assert!(client.search(&SearchQuery::default()).await.is_err());
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrency_cap() {
+        let counters = Arc::new(Counters::default());
+        let router = Router::new()
+            .route(
+                "/",
+                get(|State(c): State<Arc<Counters>>| async move {
+                    let now = c.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    c.max_in_flight.fetch_max(now, Ordering::SeqCst);
+                    c.hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    c.in_flight.fetch_sub(1, Ordering::SeqCst);
+                    axum::response::Html(listing(10 * 75))
+                }),
+            )
+            .with_state(counters.clone());
+        let client = NyaaClient::new(NyaaClientConfig {
+            max_concurrency: NonZeroUsize::new(2),
+            ..config(serve(router).await)
+        });
+        let resp = client.search(&SearchQuery::default()).await.unwrap();
+        assert_eq!(resp.last_page, 10);
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 10);
+        assert_eq!(counters.max_in_flight.load(Ordering::SeqCst), 2);
+    }
 
     #[tokio::test]
-    async fn test() {
-        let client = NyaaClient::new(Default::default());
-        let resp = client
-            .search(&crate::SearchQuery {
-                query: "".into(),
-                max_page_idx: NonZeroUsize::new(1),
-                ..Default::default()
-            })
+    async fn rate_limit_shared_across_clones() {
+        let router = Router::new().route("/", get(|| async { axum::response::Html(listing(3)) }));
+        let client = NyaaClient::new(NyaaClientConfig {
+            requests_per_second: Some(20.0),
+            ..config(serve(router).await)
+        });
+        let start = Instant::now();
+        let searches = (0..5).map(|_| {
+            let client = client.clone();
+            async move { client.search(&SearchQuery::default()).await.unwrap() }
+        });
+        futures::future::join_all(searches).await;
+        // the first request goes out immediately, the other four are spaced 50ms apart
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn search_reads_each_page_once() {
+        let (client, pages) = paginated().await;
+        let resp = client.search(&SearchQuery::default()).await.unwrap();
+        assert_eq!((resp.last_page, resp.last_read_page), (3, 3));
+        assert_eq!(resp.results.len(), 9);
+        let mut pages = pages.lock().unwrap().clone();
+        pages.sort();
+        assert_eq!(pages, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn stream_pages_in_order() {
+        let (client, pages) = paginated().await;
+        let q = SearchQuery::default();
+        let items: Vec<_> = client.search_stream(&q).try_collect().await.unwrap();
+        assert_eq!(items.len(), 9);
+        assert_eq!(*pages.lock().unwrap(), [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn stream_stops_early() {
+        let (client, pages) = paginated().await;
+        let q = SearchQuery::default();
+        let first = client
+            .search_stream(&q)
+            .take(3)
+            .try_collect::<Vec<_>>()
             .await
             .unwrap();
-        dbg!(&resp.results);
+        assert_eq!(first[0].nyaa_id, 1990001);
+        // everything taken came from the first page, so no other page was requested
+        assert_eq!(*pages.lock().unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn stream_respects_max_page_idx() {
+        let (client, pages) = paginated().await;
+        let q = SearchQuery {
+            max_page_idx: NonZeroUsize::new(2),
+            ..Default::default()
+        };
+        assert_eq!(client.search_stream(&q).count().await, 6);
+        assert_eq!(*pages.lock().unwrap(), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_on_error() {
+        let router = Router::new().route("/", get(|| async { StatusCode::NOT_FOUND }));
+        let client = NyaaClient::new(config(serve(router).await));
+        let q = SearchQuery::default();
+        let results: Vec<_> = client.search_stream(&q).collect().await;
+        assert!(matches!(
+            &results[..],
+            [Err(NyaaError::Status(StatusCode::NOT_FOUND))]
+        ));
     }
 }
@@ -0,0 +1,59 @@
+use std::{
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+
+/// Request budget shared by every clone of a [`crate::NyaaClient`]
+#[derive(Debug)]
+pub(crate) struct RequestLimiter {
+    in_flight: Option<Arc<Semaphore>>,
+    interval: Option<Duration>,
+    /// Earliest instant the next request may be sent at
+    next_slot: Mutex<Instant>,
+}
+
+/// Held for the duration of a request
+pub(crate) struct RequestPermit {
+    _in_flight: Option<OwnedSemaphorePermit>,
+}
+
+impl RequestLimiter {
+    pub(crate) fn new(
+        max_concurrency: Option<NonZeroUsize>,
+        requests_per_second: Option<f64>,
+    ) -> Self {
+        Self {
+            in_flight: max_concurrency.map(|n| Arc::new(Semaphore::new(n.get()))),
+            interval: requests_per_second
+                .filter(|rps| rps.is_finite() && *rps > 0.0)
+                .map(|rps| Duration::from_secs_f64(1.0 / rps)),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+    /// Waits until both a concurrency slot and a rate limit slot are free
+    pub(crate) async fn acquire(&self) -> RequestPermit {
+        let permit = match &self.in_flight {
+            Some(sem) => Some(
+                sem.clone()
+                    .acquire_owned()
+                    .await
+                    .expect("request semaphore to never be closed"),
+            ),
+            None => None,
+        };
+        if let Some(interval) = self.interval {
+            // reserve a slot under the lock, but sleep outside of it so later callers can queue up behind us
+            let at = {
+                let mut next_slot = self.next_slot.lock().await;
+                let at = (*next_slot).max(Instant::now());
+                *next_slot = at + interval;
+                at
+            };
+            tokio::time::sleep_until(at.into()).await;
+        }
+        RequestPermit { _in_flight: permit }
+    }
+}
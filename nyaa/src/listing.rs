@@ -0,0 +1,293 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, NaiveDateTime};
+use scraper::{ElementRef, Html, Selector};
+
+use crate::{InfoHash, Item, NyaaError, parse_size};
+
+pub(crate) const MAX_ITEMS_PER_PAGE: usize = 75;
+
+/// One page of the HTML search listing
+pub(crate) struct ListingPage {
+    pub(crate) items: Vec<Item>,
+    /// Total number of results across all pages
+    pub(crate) num_results: usize,
+}
+
+pub(crate) fn parse_listing(instance_url: &str, bytes: &[u8]) -> Result<ListingPage, NyaaError> {
+    struct ScrapeSelectors([Selector; 13]);
+    fn make_sels() -> ScrapeSelectors {
+        fn selector(s: &str) -> Selector {
+            Selector::parse(s).expect("statically specified selector to parse")
+        }
+        ScrapeSelectors([
+            selector("table.torrent-list > tbody > tr"),
+            selector("td:first-of-type > a"),
+            selector("td:nth-of-type(2) > a:last-of-type"),
+            selector("td:nth-of-type(3) > a:nth-of-type(2)"),
+            selector("td:nth-of-type(3) > a:nth-of-type(1)"),
+            selector("td:nth-of-type(4)"),
+            selector("td:nth-of-type(5)"),
+            selector("td:nth-of-type(6)"),
+            selector("td:nth-of-type(7)"),
+            selector("td:nth-of-type(8)"),
+            selector(".pagination-page-info"),
+            selector("table.torrent-list"),
+            selector("h3"),
+        ])
+    }
+    // cache selectors
+    static SELECTORS: OnceLock<ScrapeSelectors> = OnceLock::new();
+
+    let ScrapeSelectors(
+        [
+            selector_item,
+            selector_icon,
+            selector_title,
+            selector_magnet,
+            selector_torrent,
+            selector_size,
+            selector_date,
+            selector_seeders,
+            selector_leechers,
+            selector_downloads,
+            selector_pagination,
+            selector_table,
+            selector_heading,
+        ],
+    ) = SELECTORS.get_or_init(make_sels);
+
+    let html = Html::parse_document(str::from_utf8(bytes)?);
+
+    let items: Vec<_> = html
+        .select(selector_item)
+        .filter_map(|el| {
+            let select_attr = |sel, attr| el.select(sel).next().and_then(|v| v.value().attr(attr));
+
+            let select_inner = |sel| el.select(sel).next().as_ref().map(ElementRef::inner_html);
+
+            let mut torrent_file_link = String::from(instance_url);
+            let id_dot_torrent = select_attr(selector_torrent, "href")?;
+            torrent_file_link.push_str(id_dot_torrent);
+            let magnet_link = select_attr(selector_magnet, "href")?;
+            let row_class = el.value().attr("class").unwrap_or_default();
+
+            Some(Item {
+                category: select_attr(selector_icon, "href")?
+                    .split('=')
+                    .next_back()?
+                    .parse()
+                    .ok()?,
+                torrent_file_link,
+                size: parse_size(&select_inner(selector_size)?)?,
+                title: select_attr(selector_title, "title")?.into(),
+                info_hash: InfoHash::from_magnet(magnet_link)?,
+                magnet_link: magnet_link.into(),
+                date: select_attr(selector_date, "data-timestamp")
+                    .and_then(|ts| DateTime::from_timestamp(ts.parse().ok()?, 0))
+                    .or_else(|| {
+                        NaiveDateTime::parse_from_str(
+                            &select_inner(selector_date)?,
+                            "%Y-%m-%d %H:%M",
+                        )
+                        .ok()
+                        .map(|v| v.and_utc())
+                    })?,
+                seeders: select_inner(selector_seeders)?.parse().unwrap_or_default(),
+                leechers: select_inner(selector_leechers)?.parse().unwrap_or_default(),
+                downloads: select_inner(selector_downloads)?
+                    .parse()
+                    .unwrap_or_default(),
+                nyaa_id: id_dot_torrent
+                    .trim_end_matches(".torrent")
+                    .trim_start_matches("/download/")
+                    .parse()
+                    .ok()?,
+                is_trusted: row_class.contains("success"),
+                is_remake: row_class.contains("danger"),
+            })
+        })
+        .collect();
+
+    let pagination = html.select(selector_pagination).next().and_then(|v| {
+        v.inner_html()
+            .split(' ')
+            .nth(5)
+            .and_then(|v| str::parse(v).ok())
+    });
+    let num_results = match pagination {
+        Some(v) => v,
+        // no pagination info, but still a listing. Assume everything fit on this page
+        None if html.select(selector_table).next().is_some() => items.len(),
+        None if html
+            .select(selector_heading)
+            .any(|el| el.text().collect::<String>().trim() == "No results found") =>
+        {
+            0
+        }
+        None => return Err(NyaaError::UnrecognizedLayout("search results listing")),
+    };
+
+    Ok(ListingPage { items, num_results })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        AnimeKind, AudioKind, LiteratureKind, LiveActionKind, MediaCategory, NyaaError,
+        PictureKind, SoftwareKind,
+    };
+
+    use super::parse_listing;
+
+    #[test]
+    fn parse_page() {
+        let page = parse_listing(
+            "https://nyaa.si",
+            include_bytes!("../fixtures/listing.html"),
+        )
+        .unwrap();
+        assert_eq!(page.num_results, 3);
+        assert_eq!(page.items.len(), 3);
+
+        let first = &page.items[0];
+        assert_eq!(first.nyaa_id, 1990001);
+        assert_eq!(
+            &*first.title,
+            "[SubsPlease] Yagate Kimi ni Naru - 10 (1080p) [4A2B7C1D].mkv"
+        );
+        assert_eq!(
+            first.torrent_file_link,
+            "https://nyaa.si/download/1990001.torrent"
+        );
+        assert_eq!(
+            first.info_hash.to_string(),
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        assert!(matches!(
+            first.category,
+            MediaCategory::Anime(Some(AnimeKind::SubEnglish))
+        ));
+        assert_eq!(first.size, 1395 * 1024 * 1024);
+        assert_eq!(first.date.timestamp(), 1544290509);
+        assert_eq!(
+            (first.seeders, first.leechers, first.downloads),
+            (143, 4, 5021)
+        );
+        assert!(first.is_trusted);
+        assert!(!first.is_remake);
+
+        assert!(page.items[1].is_remake);
+        assert!(!page.items[2].is_trusted && !page.items[2].is_remake);
+    }
+
+    #[test]
+    fn parse_pagination() {
+        let first = parse_listing(
+            "https://nyaa.si",
+            include_bytes!("../fixtures/pagination_1.html"),
+        )
+        .unwrap();
+        let second = parse_listing(
+            "https://nyaa.si",
+            include_bytes!("../fixtures/pagination_2.html"),
+        )
+        .unwrap();
+        // both pages report the total, not the number of results on the page
+        assert_eq!(first.num_results, 80);
+        assert_eq!(second.num_results, 80);
+        assert_eq!(first.items.len(), 3);
+        assert_eq!(second.items.len(), 2);
+        assert_eq!(second.items[0].nyaa_id, 1992001);
+    }
+
+    #[test]
+    fn parse_categories() {
+        use MediaCategory::*;
+        let page = parse_listing(
+            "https://nyaa.si",
+            include_bytes!("../fixtures/categories.html"),
+        )
+        .unwrap();
+        let expected = [
+            Anime(Some(AnimeKind::MusicVideo)),
+            Anime(Some(AnimeKind::SubEnglish)),
+            Anime(Some(AnimeKind::SubNonEnglish)),
+            Anime(Some(AnimeKind::Raw)),
+            Audio(Some(AudioKind::Lossless)),
+            Audio(Some(AudioKind::Lossy)),
+            Literature(Some(LiteratureKind::TranslatedEnglish)),
+            Literature(Some(LiteratureKind::Raw)),
+            LiveAction(Some(LiveActionKind::TranslatedEnglish)),
+            LiveAction(Some(LiveActionKind::IdolPromoVideo)),
+            LiveAction(Some(LiveActionKind::TranslatedNonEnglish)),
+            LiveAction(Some(LiveActionKind::Raw)),
+            Picture(Some(PictureKind::Graphic)),
+            Picture(Some(PictureKind::Photo)),
+            Software(Some(SoftwareKind::Application)),
+            Software(Some(SoftwareKind::Game)),
+        ];
+        // the rows with unknown categories (9_9 and 1_9) are skipped
+        assert_eq!(page.items.len(), expected.len());
+        for (item, cat) in page.items.iter().zip(expected) {
+            assert_eq!(item.category.encode(), cat.encode(), "{}", item.title);
+        }
+    }
+
+    #[test]
+    fn skip_malformed_rows() {
+        let page = parse_listing(
+            "https://nyaa.si",
+            include_bytes!("../fixtures/malformed.html"),
+        )
+        .unwrap();
+        let titles: Vec<_> = page.items.iter().map(|v| &*v.title).collect();
+        assert_eq!(
+            titles,
+            [
+                "well formed row",
+                "date without timestamp",
+                "non-numeric counts"
+            ]
+        );
+        // the date falls back to the cell text
+        assert_eq!(page.items[1].date.timestamp(), 1599999960);
+        let counts = &page.items[2];
+        assert_eq!(
+            (counts.seeders, counts.leechers, counts.downloads),
+            (0, 0, 0)
+        );
+        // pagination still reports the rows that were skipped
+        assert_eq!(page.num_results, 10);
+    }
+
+    #[test]
+    fn parse_no_results() {
+        let page = parse_listing(
+            "https://nyaa.si",
+            include_bytes!("../fixtures/no_results.html"),
+        )
+        .unwrap();
+        assert_eq!(page.num_results, 0);
+        assert!(page.items.is_empty());
+    }
+
+    #[test]
+    fn reject_challenge_page() {
+        assert!(matches!(
+            parse_listing(
+                "https://nyaa.si",
+                include_bytes!("../fixtures/cloudflare.html")
+            ),
+            Err(NyaaError::UnrecognizedLayout(_))
+        ));
+    }
+
+    #[test]
+    fn reject_non_utf8() {
+        assert!(matches!(
+            parse_listing("https://nyaa.si", b"<html>\xff\xfe</html>"),
+            Err(NyaaError::NonUtf8Body(_))
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+//! In-process stand-in for a nyaa instance, serving recorded pages so [`crate::NyaaClient`] and its users can be tested offline.
+//!
+//! ```ignore
+//! let server = MockNyaa::new()
+//!     .listing("Yagate Kimi ni Naru", [fixtures::LISTING])
+//!     .torrent(1990003, fixtures::TORRENT)
+//!     .serve()
+//!     .await?;
+//! let client = NyaaClient::new(server.client_config());
+//! ```
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use bytes::Bytes;
+use tokio::task::JoinHandle;
+
+use crate::NyaaClientConfig;
+
+/// Recorded responses bundled with the crate
+pub mod fixtures {
+    /// A single page listing three episodes, one trusted, one remake and one plain upload
+    pub const LISTING: &str = include_str!("../fixtures/listing.html");
+    /// First of two pages of an 80 result search
+    pub const PAGINATION_1: &str = include_str!("../fixtures/pagination_1.html");
+    /// Second of two pages of an 80 result search
+    pub const PAGINATION_2: &str = include_str!("../fixtures/pagination_2.html");
+    /// One row for every category nyaa lists, followed by two rows with unknown categories
+    pub const CATEGORIES: &str = include_str!("../fixtures/categories.html");
+    /// Rows with missing or garbled cells, mixed in with well formed ones
+    pub const MALFORMED: &str = include_str!("../fixtures/malformed.html");
+    pub const NO_RESULTS: &str = include_str!("../fixtures/no_results.html");
+    /// What is served instead of the listing while a Cloudflare challenge is active
+    pub const CLOUDFLARE: &str = include_str!("../fixtures/cloudflare.html");
+    /// The RSS feed for the same search as [`LISTING`]
+    pub const SEARCH_RSS: &str = include_str!("../fixtures/search.rss");
+    pub const EMPTY_RSS: &str = include_str!("../fixtures/empty.rss");
+    pub const VIEW_BATCH: &str = include_str!("../fixtures/view_batch.html");
+    pub const VIEW_ANONYMOUS: &str = include_str!("../fixtures/view_anonymous.html");
+    /// Single file torrent for the third item of [`LISTING`]
+    pub const TORRENT: &[u8] = include_bytes!("../fixtures/1990003.torrent");
+}
+
+/// Builder for the set of pages a [`MockNyaaServer`] serves
+#[derive(Debug, Default, Clone)]
+pub struct MockNyaa {
+    listings: HashMap<String, Vec<String>>,
+    fallback_listing: Option<Vec<String>>,
+    feeds: HashMap<String, String>,
+    views: HashMap<u64, String>,
+    torrents: HashMap<u64, Bytes>,
+}
+
+impl MockNyaa {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Serve `pages` (in order, starting from page 1) for searches with exactly this query string
+    pub fn listing(
+        mut self,
+        query: impl Into<String>,
+        pages: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.listings
+            .insert(query.into(), pages.into_iter().map(Into::into).collect());
+        self
+    }
+    /// Serve `pages` for any search without a listing of its own. Otherwise those searches find no results
+    pub fn fallback_listing(mut self, pages: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.fallback_listing = Some(pages.into_iter().map(Into::into).collect());
+        self
+    }
+    /// Serve `feed` for RSS searches with exactly this query string. Otherwise the feed is empty
+    pub fn feed(mut self, query: impl Into<String>, feed: impl Into<String>) -> Self {
+        self.feeds.insert(query.into(), feed.into());
+        self
+    }
+    /// Serve `page` at `/view/{nyaa_id}`
+    pub fn view(mut self, nyaa_id: u64, page: impl Into<String>) -> Self {
+        self.views.insert(nyaa_id, page.into());
+        self
+    }
+    /// Serve `torrent` at `/download/{nyaa_id}.torrent`
+    pub fn torrent(mut self, nyaa_id: u64, torrent: impl Into<Bytes>) -> Self {
+        self.torrents.insert(nyaa_id, torrent.into());
+        self
+    }
+    /// Start serving on an ephemeral loopback port
+    pub async fn serve(self) -> std::io::Result<MockNyaaServer> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let state = Arc::new(self);
+        let router = Router::new()
+            .route("/", get(h_search))
+            .route("/view/{id}", get(h_view))
+            .route("/download/{file}", get(h_download))
+            .with_state(state)
+            .layer(middleware::from_fn_with_state(requests.clone(), record));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                log::error!("mock nyaa server failed: {e}");
+            }
+        });
+        Ok(MockNyaaServer {
+            addr,
+            requests,
+            task,
+        })
+    }
+}
+
+/// A running [`MockNyaa`]. The server shuts down when this is dropped
+#[derive(Debug)]
+pub struct MockNyaaServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockNyaaServer {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    /// Instance URL to point [`NyaaClientConfig::instance_url`] at
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+    /// Client config for this server, without request limits and with near-instant retries
+    pub fn client_config(&self) -> NyaaClientConfig {
+        NyaaClientConfig {
+            instance_url: self.url().into(),
+            max_concurrency: None,
+            requests_per_second: None,
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+    /// Path and query of every request received so far, in the order they arrived
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().expect("not poisoned").clone()
+    }
+}
+
+impl Drop for MockNyaaServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn record(
+    State(requests): State<Arc<Mutex<Vec<String>>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    requests.lock().expect("not poisoned").push(path);
+    next.run(req).await
+}
+
+async fn h_search(
+    State(mock): State<Arc<MockNyaa>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let query = params.get("q").map(String::as_str).unwrap_or_default();
+    if params.get("page").is_some_and(|v| v == "rss") {
+        let feed = mock
+            .feeds
+            .get(query)
+            .map(String::as_str)
+            .unwrap_or(fixtures::EMPTY_RSS);
+        return (
+            [(header::CONTENT_TYPE, "application/xml")],
+            feed.to_string(),
+        )
+            .into_response();
+    }
+
+    let Some(pages) = mock.listings.get(query).or(mock.fallback_listing.as_ref()) else {
+        return Html(fixtures::NO_RESULTS).into_response();
+    };
+    // like nyaa, treat a missing or zero page as the first one
+    let page = params
+        .get("p")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+    match pages.get(page - 1) {
+        Some(v) => Html(v.clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn h_view(State(mock): State<Arc<MockNyaa>>, Path(id): Path<u64>) -> Response {
+    match mock.views.get(&id) {
+        Some(v) => Html(v.clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn h_download(State(mock): State<Arc<MockNyaa>>, Path(file): Path<String>) -> Response {
+    let torrent = file
+        .strip_suffix(".torrent")
+        .and_then(|id| id.parse().ok())
+        .and_then(|id: u64| mock.torrents.get(&id));
+    match torrent {
+        Some(v) => (
+            [(header::CONTENT_TYPE, "application/x-bittorrent")],
+            v.clone(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::DateTime;
+use roxmltree::{Document, Node};
+
+use crate::{Item, NyaaError, parse_size};
+
+const NYAA_NS: &str = "https://nyaa.si/xmlns/nyaa";
+
+/// Trackers nyaa appends to the magnet links it renders in the HTML listing
+const TRACKERS: [&str; 5] = [
+    "http://nyaa.tracker.wf:7777/announce",
+    "udp://open.stealth.si:80/announce",
+    "udp://tracker.opentrackr.org:1337/announce",
+    "udp://exodus.desync.com:6969/announce",
+    "udp://tracker.torrent.eu.org:451/announce",
+];
+
+/// Parses the items of a nyaa `?page=rss` feed. Items missing required fields are skipped, like malformed rows in the HTML listing.
+pub(crate) fn parse_feed(text: &str) -> Result<Vec<Item>, NyaaError> {
+    let doc = Document::parse(text)?;
+    Ok(doc
+        .descendants()
+        .filter(|n| n.has_tag_name("item"))
+        .filter_map(parse_item)
+        .collect())
+}
+
+fn parse_item(item: Node<'_, '_>) -> Option<Item> {
+    let child = |name: &str| {
+        item.children()
+            .find(|n| n.has_tag_name(name))
+            .and_then(|n| n.text())
+            .map(str::trim)
+    };
+    let nyaa = |name: &str| {
+        item.children()
+            .find(|n| n.has_tag_name((NYAA_NS, name)))
+            .and_then(|n| n.text())
+            .map(str::trim)
+    };
+
+    let title = child("title")?;
+    let info_hash = nyaa("infoHash")?;
+    Some(Item {
+        nyaa_id: child("guid")?.rsplit('/').next()?.parse().ok()?,
+        seeders: nyaa("seeders")?.parse().unwrap_or_default(),
+        leechers: nyaa("leechers")?.parse().unwrap_or_default(),
+        downloads: nyaa("downloads")?.parse().unwrap_or_default(),
+        category: nyaa("categoryId")?.parse().ok()?,
+        magnet_link: magnet_link(info_hash, title).into(),
+        info_hash: info_hash.parse().ok()?,
+        torrent_file_link: child("link")?.to_string(),
+        title: title.into(),
+        size: parse_size(nyaa("size")?)?,
+        date: DateTime::parse_from_rfc2822(child("pubDate")?)
+            .ok()?
+            .to_utc(),
+        is_trusted: nyaa("trusted")? == "Yes",
+        is_remake: nyaa("remake")? == "Yes",
+    })
+}
+
+/// The feed only carries the info hash, so rebuild the magnet link nyaa would have rendered
+fn magnet_link(info_hash: &str, title: &str) -> String {
+    let mut link = format!(
+        "magnet:?xt=urn:btih:{info_hash}&dn={}",
+        urlencoding::encode(title)
+    );
+    for tracker in TRACKERS {
+        link.push_str("&tr=");
+        link.push_str(&urlencoding::encode(tracker));
+    }
+    link
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{AnimeKind, MediaCategory};
+
+    use super::parse_feed;
+
+    #[test]
+    fn parse_search_feed() {
+        let items = parse_feed(include_str!("../fixtures/search.rss")).unwrap();
+        // the third item has no info hash and is skipped
+        assert_eq!(items.len(), 2);
+
+        let first = &items[0];
+        assert_eq!(first.nyaa_id, 1990001);
+        assert_eq!(
+            &*first.title,
+            "[SubsPlease] Yagate Kimi ni Naru - 10 (1080p) [4A2B7C1D].mkv"
+        );
+        assert_eq!(
+            first.torrent_file_link,
+            "https://nyaa.si/download/1990001.torrent"
+        );
+        assert!(first.magnet_link.starts_with(
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=%5BSubsPlease%5D"
+        ));
+        assert_eq!(first.seeders, 143);
+        assert_eq!(first.leechers, 4);
+        assert_eq!(first.downloads, 5021);
+        assert!(matches!(
+            first.category,
+            MediaCategory::Anime(Some(AnimeKind::SubEnglish))
+        ));
+        assert_eq!(first.size, 1395 * 1024 * 1024);
+        assert_eq!(first.date.timestamp(), 1544290509);
+        assert_eq!(
+            first.info_hash.to_string(),
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        assert!(first.is_trusted);
+        assert!(!first.is_remake);
+
+        let second = &items[1];
+        assert_eq!(second.nyaa_id, 1990002);
+        assert!(matches!(
+            second.category,
+            MediaCategory::Anime(Some(AnimeKind::Raw))
+        ));
+        assert_eq!(second.size, 512 * 1024 * 1024);
+        assert!(!second.is_trusted);
+        assert!(second.is_remake);
+    }
+
+    #[test]
+    fn parse_empty_feed() {
+        assert!(
+            parse_feed(include_str!("../fixtures/empty.rss"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn reject_non_xml() {
+        assert!(parse_feed("<html><body>503 Service Unavailable").is_err());
+    }
+}
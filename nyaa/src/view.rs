@@ -0,0 +1,362 @@
+use std::sync::OnceLock;
+
+use crate::{InfoHash, MediaCategory, NyaaError, parse_size};
+use scraper::{ElementRef, Html, Selector};
+
+/// Contents of a torrent's `/view/{id}` page
+#[derive(Debug, Clone)]
+pub struct TorrentDetails {
+    pub nyaa_id: u64,
+    pub title: Box<str>,
+    pub category: MediaCategory,
+    pub info_hash: InfoHash,
+    /// `None` if the torrent was uploaded anonymously
+    pub submitter: Option<Box<str>>,
+    pub is_trusted: bool,
+    pub is_remake: bool,
+    /// The "Information" link provided by the uploader, usually a group website or IRC channel
+    pub information: Option<Box<str>>,
+    pub size: Option<u64>,
+    pub seeders: u32,
+    pub leechers: u32,
+    pub downloads: u32,
+    pub magnet_link: Box<str>,
+    pub torrent_file_link: String,
+    /// Raw markdown source of the description
+    pub description: Box<str>,
+    pub comment_count: u32,
+    /// Top level of the file tree
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FileEntry {
+    File {
+        name: Box<str>,
+        size: Option<u64>,
+    },
+    Folder {
+        name: Box<str>,
+        children: Vec<FileEntry>,
+    },
+}
+
+impl TorrentDetails {
+    /// Flattens the file tree into `/`-separated paths and sizes, in the order nyaa lists them.
+    /// Note that nyaa sorts the listing, so this order does not necessarily match the torrent's file indices.
+    pub fn file_paths(&self) -> Vec<(String, Option<u64>)> {
+        fn walk(prefix: &str, entries: &[FileEntry], out: &mut Vec<(String, Option<u64>)>) {
+            for entry in entries {
+                match entry {
+                    FileEntry::File { name, size } => out.push((format!("{prefix}{name}"), *size)),
+                    FileEntry::Folder { name, children } => {
+                        walk(&format!("{prefix}{name}/"), children, out)
+                    }
+                }
+            }
+        }
+        let mut out = Vec::new();
+        walk("", &self.files, &mut out);
+        out
+    }
+}
+
+fn text(el: ElementRef<'_>) -> String {
+    el.text().collect::<String>().trim().to_string()
+}
+
+pub(crate) fn parse_view(
+    nyaa_id: u64,
+    instance_url: &str,
+    page: &str,
+) -> Result<TorrentDetails, NyaaError> {
+    struct ViewSelectors([Selector; 10]);
+    fn make_sels() -> ViewSelectors {
+        fn selector(s: &str) -> Selector {
+            Selector::parse(s).expect("statically specified selector to parse")
+        }
+        ViewSelectors([
+            selector("div.container > div.panel"),
+            selector(".panel-title"),
+            selector(".panel-body div.col-md-1"),
+            selector("kbd"),
+            selector("a"),
+            selector(".panel-footer a"),
+            selector("#torrent-description"),
+            selector("#comments .panel-title"),
+            selector("#comments .comment-panel"),
+            selector(".torrent-file-list > ul"),
+        ])
+    }
+    static SELECTORS: OnceLock<ViewSelectors> = OnceLock::new();
+    let ViewSelectors(
+        [
+            selector_panel,
+            selector_title,
+            selector_label,
+            selector_info_hash,
+            selector_link,
+            selector_footer_link,
+            selector_description,
+            selector_comments_heading,
+            selector_comment,
+            selector_file_list,
+        ],
+    ) = SELECTORS.get_or_init(make_sels);
+
+    let html = Html::parse_document(page);
+    let missing = NyaaError::UnrecognizedLayout;
+
+    let panel = html.select(selector_panel).next().ok_or(missing("panel"))?;
+    let classes = panel.value().attr("class").unwrap_or_default();
+    let is_remake = classes.contains("panel-danger");
+    let is_trusted = classes.contains("panel-success");
+
+    // the details are laid out as `label: value` pairs of columns
+    let field = |label: &str| {
+        panel
+            .select(selector_label)
+            .find(|el| text(*el) == label)
+            .and_then(|el| el.next_siblings().find_map(ElementRef::wrap))
+    };
+    let number = |label| {
+        field(label)
+            .and_then(|el| text(el).parse().ok())
+            .unwrap_or_default()
+    };
+
+    let title = panel
+        .select(selector_title)
+        .next()
+        .map(text)
+        .ok_or(missing("title"))?;
+    let category = field("Category:")
+        .and_then(|el| el.select(selector_link).last())
+        .and_then(|el| el.value().attr("href"))
+        .and_then(|href| href.split('=').next_back())
+        .and_then(|v| v.parse().ok())
+        .ok_or(missing("category"))?;
+    let info_hash = panel
+        .select(selector_info_hash)
+        .next()
+        .map(text)
+        .ok_or(missing("info hash"))?
+        .parse()?;
+    let submitter = field("Submitter:")
+        .ok_or(missing("submitter"))?
+        .select(selector_link)
+        .next()
+        .map(|el| text(el).into());
+    let information = field("Information:")
+        .and_then(|el| el.select(selector_link).next())
+        .and_then(|el| el.value().attr("href"))
+        .map(Into::into);
+
+    let footer_link = |prefix: &str| {
+        panel
+            .select(selector_footer_link)
+            .filter_map(|el| el.value().attr("href"))
+            .find(|href| href.starts_with(prefix))
+    };
+    let magnet_link = footer_link("magnet:").ok_or(missing("magnet link"))?.into();
+    let torrent_file_link = footer_link("/download/")
+        .map(|path| format!("{instance_url}{path}"))
+        .ok_or(missing("torrent file link"))?;
+
+    let description = html
+        .select(selector_description)
+        .next()
+        .map(|el| el.text().collect::<String>().into())
+        .unwrap_or_default();
+
+    let comment_count = html
+        .select(selector_comments_heading)
+        .next()
+        .and_then(|heading| text(heading).rsplit(' ').next()?.parse().ok())
+        // fall back to counting the comments if the heading is missing
+        .unwrap_or_else(|| html.select(selector_comment).count() as u32);
+
+    let files = html
+        .select(selector_file_list)
+        .next()
+        .map(parse_file_list)
+        .unwrap_or_default();
+
+    Ok(TorrentDetails {
+        nyaa_id,
+        title: title.into(),
+        category,
+        info_hash,
+        submitter,
+        is_trusted,
+        is_remake,
+        information,
+        size: field("File size:").and_then(|el| parse_size(&text(el))),
+        seeders: number("Seeders:"),
+        leechers: number("Leechers:"),
+        downloads: number("Completed:"),
+        magnet_link,
+        torrent_file_link,
+        description,
+        comment_count,
+        files,
+    })
+}
+
+/// Walks a `<ul>` of the file tree. Folders are `<li>`s holding an `a.folder` and a nested `<ul>`, files are `<li>`s holding the name and a `span.file-size`
+fn parse_file_list(ul: ElementRef<'_>) -> Vec<FileEntry> {
+    fn child_elements<'a>(el: ElementRef<'a>) -> impl Iterator<Item = ElementRef<'a>> {
+        el.children().filter_map(ElementRef::wrap)
+    }
+    child_elements(ul)
+        .filter(|li| li.value().name() == "li")
+        .map(|li| {
+            let folder = child_elements(li).find(|el| {
+                el.value().name() == "a"
+                    && el
+                        .value()
+                        .has_class("folder", scraper::CaseSensitivity::CaseSensitive)
+            });
+            match folder {
+                Some(a) => FileEntry::Folder {
+                    name: text(a).into(),
+                    children: child_elements(li)
+                        .find(|el| el.value().name() == "ul")
+                        .map(parse_file_list)
+                        .unwrap_or_default(),
+                },
+                None => FileEntry::File {
+                    name: li
+                        .children()
+                        .filter_map(|n| n.value().as_text())
+                        .map(|t| &**t)
+                        .collect::<String>()
+                        .trim()
+                        .into(),
+                    size: child_elements(li)
+                        .find(|el| {
+                            el.value()
+                                .has_class("file-size", scraper::CaseSensitivity::CaseSensitive)
+                        })
+                        .and_then(|el| parse_size(&text(el))),
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{AnimeKind, MediaCategory};
+
+    use super::{FileEntry, parse_view};
+
+    #[test]
+    fn parse_batch() {
+        let details = parse_view(
+            1990010,
+            "https://nyaa.si",
+            include_str!("../fixtures/view_batch.html"),
+        )
+        .unwrap();
+        assert_eq!(
+            &*details.title,
+            "[Judas] Yagate Kimi ni Naru (Season 1) [BD 1080p][HEVC x265 10bit][Eng-Subs] (Batch)"
+        );
+        assert!(matches!(
+            details.category,
+            MediaCategory::Anime(Some(AnimeKind::SubEnglish))
+        ));
+        assert_eq!(
+            details.info_hash.to_string(),
+            "fedcba9876543210fedcba9876543210fedcba98"
+        );
+        assert_eq!(details.submitter.as_deref(), Some("Judas"));
+        assert!(details.is_trusted);
+        assert!(!details.is_remake);
+        assert_eq!(
+            details.information.as_deref(),
+            Some("https://discord.gg/example")
+        );
+        assert_eq!(details.seeders, 57);
+        assert_eq!(details.leechers, 3);
+        assert_eq!(details.downloads, 10342);
+        assert_eq!(
+            details.torrent_file_link,
+            "https://nyaa.si/download/1990010.torrent"
+        );
+        assert!(
+            details
+                .magnet_link
+                .starts_with("magnet:?xt=urn:btih:fedcba98")
+        );
+        assert!(
+            details
+                .description
+                .contains("#### Batch of the first season")
+        );
+        assert!(details.description.contains("**Video:**"));
+        assert_eq!(details.comment_count, 2);
+
+        let [FileEntry::Folder { name, children }] = &details.files[..] else {
+            panic!("expected a single root folder, got {:#?}", details.files);
+        };
+        assert_eq!(&**name, "[Judas] Yagate Kimi ni Naru (Season 1)");
+        assert_eq!(children.len(), 3);
+        let paths = details.file_paths();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(
+            paths[0],
+            (
+                "[Judas] Yagate Kimi ni Naru (Season 1)/Extras/[Judas] Yagate Kimi ni Naru - NCOP.mkv".to_string(),
+                Some(50 * 1024 * 1024)
+            )
+        );
+        assert_eq!(
+            paths[2].0,
+            "[Judas] Yagate Kimi ni Naru (Season 1)/[Judas] Yagate Kimi ni Naru - S01E02.mkv"
+        );
+    }
+
+    #[test]
+    fn count_comments_without_heading() {
+        let page = include_str!("../fixtures/view_batch.html")
+            .replace(r#"<h3 class="panel-title">Comments - 2</h3>"#, "");
+        let details = parse_view(1990010, "https://nyaa.si", &page).unwrap();
+        assert_eq!(details.comment_count, 2);
+    }
+
+    #[test]
+    fn parse_anonymous_remake() {
+        let details = parse_view(
+            1990011,
+            "https://nyaa.si",
+            include_str!("../fixtures/view_anonymous.html"),
+        )
+        .unwrap();
+        assert_eq!(details.submitter, None);
+        assert!(details.is_remake);
+        assert!(!details.is_trusted);
+        assert_eq!(details.information, None);
+        assert_eq!(details.comment_count, 0);
+        assert_eq!(
+            details.file_paths(),
+            vec![(
+                "Yagate Kimi ni Naru - 10 [720p].mp4".to_string(),
+                Some(300 * 1024 * 1024)
+            )]
+        );
+    }
+
+    #[test]
+    fn reject_unknown_layout() {
+        assert!(
+            parse_view(
+                1,
+                "https://nyaa.si",
+                "<html><body>404 Not Found</body></html>"
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::num::NonZeroUsize;
+
+use futures::TryStreamExt;
+use nyaa::{
+    MediaCategory, NyaaClient, NyaaClientConfig, NyaaError, SearchMode, SearchQuery,
+    mock::{MockNyaa, fixtures},
+};
+
+fn query(q: &str) -> SearchQuery {
+    SearchQuery {
+        query: q.into(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn search_listing() {
+    let server = MockNyaa::new()
+        .listing("Yagate Kimi ni Naru", [fixtures::LISTING])
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(server.client_config());
+
+    let resp = client.search(&query("Yagate Kimi ni Naru")).await.unwrap();
+    assert_eq!((resp.last_page, resp.last_read_page), (1, 1));
+    assert_eq!(resp.results.len(), 3);
+    assert_eq!(
+        resp.results[0].torrent_file_link,
+        format!("{}/download/1990001.torrent", server.url())
+    );
+
+    let resp = client.search(&query("something else")).await.unwrap();
+    assert!(resp.results.is_empty());
+}
+
+#[tokio::test]
+async fn search_pagination() {
+    let server = MockNyaa::new()
+        .fallback_listing([fixtures::PAGINATION_1, fixtures::PAGINATION_2])
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(server.client_config());
+
+    let resp = client.search(&query("Yagate")).await.unwrap();
+    assert_eq!((resp.last_page, resp.last_read_page), (2, 2));
+    let ids: Vec<_> = resp.results.iter().map(|v| v.nyaa_id).collect();
+    assert_eq!(ids, [1991001, 1991002, 1991003, 1992001, 1992002]);
+
+    let pages: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|r| r.rsplit_once("&p=").map(|(_, p)| p.to_string()))
+        .collect();
+    assert_eq!(pages, ["1", "2"]);
+}
+
+#[tokio::test]
+async fn search_max_page_idx() {
+    let server = MockNyaa::new()
+        .fallback_listing([fixtures::PAGINATION_1, fixtures::PAGINATION_2])
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(server.client_config());
+
+    let q = SearchQuery {
+        max_page_idx: NonZeroUsize::new(1),
+        ..query("Yagate")
+    };
+    let resp = client.search(&q).await.unwrap();
+    assert_eq!((resp.last_page, resp.last_read_page), (2, 1));
+    assert_eq!(resp.results.len(), 3);
+    assert_eq!(server.requests().len(), 1);
+
+    let streamed: Vec<_> = client.search_stream(&q).try_collect().await.unwrap();
+    assert_eq!(streamed.len(), 3);
+}
+
+#[tokio::test]
+async fn search_category_in_query() {
+    let server = MockNyaa::new().serve().await.unwrap();
+    let client = NyaaClient::new(server.client_config());
+    let q = SearchQuery {
+        category: "1_4".parse::<MediaCategory>().unwrap(),
+        ..query("Yagate")
+    };
+    client.search(&q).await.unwrap();
+    assert!(server.requests()[0].contains("&c=1_4&"));
+}
+
+#[tokio::test]
+async fn search_challenge_page() {
+    let server = MockNyaa::new()
+        .fallback_listing([fixtures::CLOUDFLARE])
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(server.client_config());
+    assert!(matches!(
+        client.search(&query("Yagate")).await,
+        Err(NyaaError::UnrecognizedLayout(_))
+    ));
+}
+
+#[tokio::test]
+async fn search_rss_with_fallback() {
+    let server = MockNyaa::new()
+        .feed("Yagate Kimi ni Naru", fixtures::SEARCH_RSS)
+        .listing("Yagate Kimi ni Naru", [fixtures::LISTING])
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(NyaaClientConfig {
+        search_mode: SearchMode::Rss,
+        ..server.client_config()
+    });
+    let resp = client.search(&query("Yagate Kimi ni Naru")).await.unwrap();
+    assert_eq!(resp.results.len(), 2);
+    assert!(server.requests()[0].contains("page=rss"));
+
+    // a broken feed falls back to the listing
+    let server = MockNyaa::new()
+        .feed("Yagate Kimi ni Naru", "<html>not a feed")
+        .listing("Yagate Kimi ni Naru", [fixtures::LISTING])
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(NyaaClientConfig {
+        search_mode: SearchMode::RssWithHtmlFallback,
+        ..server.client_config()
+    });
+    let resp = client.search(&query("Yagate Kimi ni Naru")).await.unwrap();
+    assert_eq!(resp.results.len(), 3);
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn view_and_download() {
+    let server = MockNyaa::new()
+        .view(1990010, fixtures::VIEW_BATCH)
+        .torrent(1990003, fixtures::TORRENT)
+        .serve()
+        .await
+        .unwrap();
+    let client = NyaaClient::new(server.client_config());
+
+    let details = client.view(1990010).await.unwrap();
+    assert_eq!(details.file_paths().len(), 3);
+    assert!(matches!(
+        client.view(1).await,
+        Err(NyaaError::Status(reqwest::StatusCode::NOT_FOUND))
+    ));
+
+    let torrent = reqwest::get(format!("{}/download/1990003.torrent", server.url()))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    assert_eq!(&torrent[..], fixtures::TORRENT);
+}
@@ -0,0 +1,100 @@
+//! How a session deals with the swarm, apart from where it keeps data
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    num::NonZeroU32,
+    time::Duration,
+};
+
+use librqbit::{ListenerOptions, ManagedTorrent, SessionOptions, limits::LimitsConfig};
+use serde::{Deserialize, Serialize};
+
+/// How often a seeding torrent's ratio is looked at
+const SEED_POLL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RqstreamConfig {
+    /// bytes per second sent to peers across all torrents, unlimited if unset
+    pub upload_limit: Option<NonZeroU32>,
+    /// bytes per second received from peers across all torrents, unlimited if unset
+    pub download_limit: Option<NonZeroU32>,
+    /// what happens to a torrent once nothing streams it anymore. Ignored with [`crate::StorageConfig::InMemory`]
+    pub seed: SeedPolicy,
+    /// port peers can connect to us on, no incoming connections if unset
+    pub listen_port: Option<u16>,
+    /// find peers through the DHT as well as trackers
+    pub dht: bool,
+    /// keep the DHT routing table between sessions, so finding peers starts faster
+    pub dht_persistence: bool,
+    /// torrents in the session at once. Seeding torrents make room for new ones, past that adding one fails
+    pub max_torrents: Option<usize>,
+}
+
+impl Default for RqstreamConfig {
+    fn default() -> Self {
+        Self {
+            upload_limit: None,
+            download_limit: None,
+            seed: SeedPolicy::default(),
+            listen_port: None,
+            dht: true,
+            dht_persistence: false,
+            max_torrents: None,
+        }
+    }
+}
+
+/// Seeding after the last stream and lease of a torrent are gone, until either limit is reached. With neither set
+/// the torrent is removed right away. Only applies to [`crate::StorageConfig::Disk`] and
+/// [`crate::StorageConfig::Hybrid`] storage
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeedPolicy {
+    /// uploaded bytes per downloaded byte
+    pub ratio: Option<f64>,
+    /// seconds spent seeding
+    pub time_secs: Option<u64>,
+}
+
+impl RqstreamConfig {
+    pub(crate) fn apply(&self, opts: &mut SessionOptions) {
+        opts.disable_dht = !self.dht;
+        opts.disable_dht_persistence = !self.dht_persistence;
+        opts.ratelimits = LimitsConfig {
+            upload_bps: self.upload_limit,
+            download_bps: self.download_limit,
+        };
+        opts.listen = self.listen_port.map(|port| ListenerOptions {
+            listen_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+            ..Default::default()
+        });
+    }
+}
+
+impl SeedPolicy {
+    pub(crate) fn enabled(&self) -> bool {
+        self.ratio.is_some() || self.time_secs.is_some()
+    }
+
+    /// Wait until `torrent` has seeded enough
+    pub(crate) async fn wait(&self, torrent: &ManagedTorrent) {
+        let ratio_reached = async {
+            let Some(ratio) = self.ratio else {
+                return std::future::pending().await;
+            };
+            loop {
+                let stats = torrent.stats();
+                if stats.uploaded_bytes as f64 >= ratio * stats.progress_bytes as f64 {
+                    return;
+                }
+                tokio::time::sleep(SEED_POLL).await;
+            }
+        };
+        match self.time_secs {
+            Some(secs) => {
+                let _ = tokio::time::timeout(Duration::from_secs(secs), ratio_reached).await;
+            }
+            None => ratio_reached.await,
+        }
+    }
+}
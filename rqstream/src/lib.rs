@@ -1,68 +1,226 @@
-use std::{collections::HashMap, io::SeekFrom, iter::repeat_with, path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
 
 use anyhow::Context;
 use axum::{
-    Router,
+    Json, Router,
+    body::Body,
     extract::{Path, State},
     response::{IntoResponse, Response},
     routing::get,
 };
-use http::{HeaderMap, HeaderValue, StatusCode};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
 use librqbit::{
-    AddTorrent, AddTorrentOptions, ListOnlyResponse, ManagedTorrent, Session, SessionOptions,
+    AddTorrent, AddTorrentOptions, AddTorrentResponse, ListOnlyResponse, ManagedTorrent, Session,
+    SessionOptions,
     dht::Id20,
-    storage::{StorageFactory, StorageFactoryExt},
+    storage::{StorageFactoryExt, filesystem::FilesystemStorageFactory},
 };
 use mime_guess::Mime;
+use serde::Serialize;
 use slab::Slab;
 use tokio::{
-    io::AsyncSeekExt,
+    io::{AsyncRead, AsyncReadExt},
     net::{TcpListener, ToSocketAddrs},
     sync::{Mutex, RwLock},
 };
-use tokio_util::io::ReaderStream;
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+
+mod config;
+mod range;
+mod readahead;
+mod source;
+mod stats;
+mod storage;
+pub use config::{RqstreamConfig, SeedPolicy};
+use range::Ranges;
+use readahead::Readers;
+pub use readahead::{ReadAheadConfig, ReadAheadWindow};
+use source::{HttpSource, StreamSource};
+pub use stats::StreamStats;
+pub use storage::{
+    HybridStorage, HybridStorageFactory, InMemStorage, InMemStorageFactory, MemoryLimit,
+    StorageConfig,
+};
 
 pub struct StreamingFile {
     path: Arc<str>,
     mime: Mime,
-    torrent: Arc<ManagedTorrent>,
-    file_id: usize,
+    source: Arc<StreamSource>,
 }
 
 pub struct Rqstream {
     pub session: Arc<Session>,
+    local_addr: SocketAddr,
+    read_ahead: ReadAheadConfig,
+    readers: Arc<Readers>,
+    /// fetches upstream URLs of [`Rqstream::stream_http`]
+    http: reqwest::Client,
+    config: RqstreamConfig,
     routes: RwLock<HashMap<Arc<str>, StreamId>>,
     streaming_files: RwLock<Slab<StreamingFile>>,
-    torrent_refcounts: Mutex<HashMap<Id20, u32>>,
+    /// of each torrent in the session
+    torrent_refs: Mutex<HashMap<Id20, TorrentRefs>>,
+}
+
+/// What keeps a torrent in the session
+#[derive(Default)]
+struct TorrentRefs {
+    /// leases and streams
+    count: u32,
+    /// file being kept by [`Rqstream::download`], whose data outlives the torrent
+    download: Option<usize>,
+    /// set while nothing references it and it is seeding, see [`SeedPolicy`]
+    seeding: Option<Seeding>,
+}
+
+struct Seeding {
+    torrent: Arc<ManagedTorrent>,
+    since: Instant,
+    /// cancelled when it is needed again, or removed to make room
+    cancel: CancellationToken,
 }
 
 impl Rqstream {
-    pub async fn create(host: impl ToSocketAddrs) -> anyhow::Result<Arc<Self>> {
-        let session = Session::new_with_opts(
-            PathBuf::new(),
-            SessionOptions {
-                disable_dht_persistence: true,
-                default_storage_factory: InMemStorageFactory.boxed().into(),
-                ..Default::default()
-            },
-        )
-        .await?;
+    /// Start a session keeping torrent data as configured by `storage`, serving streams on `host` and
+    /// prefetching ahead of their readers as configured by `read_ahead`.
+    ///
+    /// Binding port 0 picks a free port, see [`Rqstream::local_addr`] for the one actually used
+    pub async fn create(
+        host: impl ToSocketAddrs,
+        storage: StorageConfig,
+        read_ahead: ReadAheadConfig,
+    ) -> anyhow::Result<Arc<Self>> {
+        Self::create_with_config(host, storage, read_ahead, RqstreamConfig::default()).await
+    }
+
+    /// Like [`Rqstream::create`], dealing with the swarm as configured by `config`
+    pub async fn create_with_config(
+        host: impl ToSocketAddrs,
+        storage: StorageConfig,
+        read_ahead: ReadAheadConfig,
+        mut config: RqstreamConfig,
+    ) -> anyhow::Result<Arc<Self>> {
+        if config.seed.enabled() && matches!(storage, StorageConfig::InMemory(_)) {
+            log::warn!("seeding needs torrent data on disk, not seeding with in-memory storage");
+            config.seed = SeedPolicy::default();
+        }
+        let output_folder = match storage.cache_dir() {
+            Some(dir) => {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| format!("create cache dir {}", dir.display()))?;
+                dir.clone()
+            }
+            None => PathBuf::new(),
+        };
+        let listener = TcpListener::bind(host)
+            .await
+            .context("bind stream server")?;
+        let local_addr = listener.local_addr()?;
+        let readers = Arc::new(Readers::default());
+        let mut opts = SessionOptions {
+            default_storage_factory: Some(storage.factory(&readers)),
+            ..Default::default()
+        };
+        config.apply(&mut opts);
+        let session = Session::new_with_opts(output_folder, opts).await?;
         let this = Arc::new(Self {
             session,
+            local_addr,
+            read_ahead,
+            readers,
+            http: reqwest::Client::new(),
+            config,
             routes: HashMap::new().into(),
             streaming_files: Slab::new().into(),
-            torrent_refcounts: HashMap::new().into(),
+            torrent_refs: HashMap::new().into(),
         });
         let route = Router::new()
             .route("/stream/{id}", get(h_http_stream))
+            .route("/status", get(h_status))
+            .route("/status/{id}", get(h_stream_status))
             .with_state(Arc::clone(&this));
-        let listener = TcpListener::bind(host).await?;
         tokio::spawn(async { axum::serve(listener, route).await.unwrap() });
         Ok(this)
     }
 
+    /// The address the stream server is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The URL a stream started with `path_name` is served at
+    pub fn route_url(&self, path_name: &str) -> String {
+        // a wildcard address cannot be connected to, go through loopback instead
+        let ip = match self.local_addr.ip() {
+            IpAddr::V4(v) if v.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(v) if v.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        format!(
+            "http://{}/stream/{path_name}",
+            SocketAddr::new(ip, self.local_addr.port())
+        )
+    }
+
+    /// The URL `id` is served at, `None` once it was stopped
+    pub async fn stream_url(&self, id: StreamId) -> Option<String> {
+        let streams = self.streaming_files.read().await;
+        Some(self.route_url(&streams.get(id.0)?.path))
+    }
+
+    /// Download progress of the file behind `id`, `None` once it was stopped or if it is not served from a torrent
+    pub async fn stream_stats(&self, id: StreamId) -> Option<StreamStats> {
+        let (path, source) = {
+            let streams = self.streaming_files.read().await;
+            let file = streams.get(id.0)?;
+            (Arc::clone(&file.path), Arc::clone(&file.source))
+        };
+        let StreamSource::Torrent {
+            torrent, file_id, ..
+        } = &*source
+        else {
+            return None;
+        };
+        let file_id = *file_id;
+        let len = torrent
+            .metadata
+            .load()
+            .as_ref()?
+            .file_infos
+            .get(file_id)?
+            .len;
+        let buffered = self.readers.buffered(id);
+        Some(StreamStats::collect(
+            id, path, torrent, file_id, len, buffered,
+        ))
+    }
+
+    /// [`Rqstream::stream_stats`] of every stream being served
+    pub async fn all_stream_stats(&self) -> Vec<StreamStats> {
+        let ids: Vec<_> = self
+            .streaming_files
+            .read()
+            .await
+            .iter()
+            .map(|(id, _)| StreamId(id))
+            .collect();
+        let mut stats = Vec::with_capacity(ids.len());
+        for id in ids {
+            stats.extend(self.stream_stats(id).await);
+        }
+        stats
+    }
+
     pub async fn get_info(&self, torrent: AddTorrent<'_>) -> anyhow::Result<ListOnlyResponse> {
-        let librqbit::AddTorrentResponse::ListOnly(list) = self
+        let AddTorrentResponse::ListOnly(list) = self
             .session
             .add_torrent(
                 torrent,
@@ -78,93 +236,570 @@ impl Rqstream {
         Ok(list)
     }
 
+    /// Serve `file_id` of `torrent` under `/stream/{path_name}`, or a variant of it if another stream already uses
+    /// that route. See [`Rqstream::stream_url`] for where it ended up.
+    ///
+    /// Route names must be non-empty and only contain unreserved URL characters. The stream keeps the torrent in the
+    /// session until it is stopped, even if `lease` is dropped before that
     pub async fn stream_file(
         &self,
-        torrent: &Arc<ManagedTorrent>,
+        lease: &TorrentLease,
         file_id: usize,
         path_name: String,
     ) -> anyhow::Result<StreamId> {
-        let to_path = path_name.into();
+        check_route(&path_name)?;
+        let torrent = lease.torrent();
         torrent.wait_until_initialized().await?;
-        self.session
-            .update_only_files(torrent, &[file_id].into())
-            .await?;
+        let download = self
+            .torrent_refs
+            .lock()
+            .await
+            .get(&torrent.info_hash())
+            .and_then(|v| v.download);
+        // other streams and a download of the torrent have to go on
+        let mut files: HashSet<usize> = self.streamed_files(torrent).await;
+        files.extend([file_id].into_iter().chain(download));
+        self.session.update_only_files(torrent, &files).await?;
         if torrent.is_paused() {
             self.session.unpause(torrent).await?;
         }
-        let mime = mime_guess::from_path(
-            &torrent
-                .metadata
-                .load()
+        let (mime, len) = {
+            let meta = torrent.metadata.load();
+            let file = meta
                 .as_ref()
                 .context("get meta")?
-                .file_infos[file_id]
-                .relative_filename,
-        )
-        .first_or_octet_stream();
+                .file_infos
+                .get(file_id)
+                .context("no such file")?;
+            (
+                mime_guess::from_path(&file.relative_filename).first_or_octet_stream(),
+                file.len,
+            )
+        };
         let torrent = Arc::clone(torrent);
 
-        let mut streams = self.streaming_files.write().await;
-        let id = StreamId(streams.insert(StreamingFile {
-            path: Arc::clone(&to_path),
-            mime,
+        let cancel = CancellationToken::new();
+        if self.read_ahead.tail > 0 {
+            let torrent = Arc::clone(&torrent);
+            let tail = self.read_ahead.tail;
+            let cancelled = cancel.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = cancelled.cancelled() => {}
+                    res = readahead::prefetch_tail(torrent, file_id, len, tail) => {
+                        if let Err(e) = res {
+                            log::warn!("tail prefetch failed: {e:#}");
+                        }
+                    }
+                }
+            });
+        }
+
+        self.torrent_refs
+            .lock()
+            .await
+            .entry(torrent.info_hash())
+            .or_default()
+            .count += 1;
+        let source = StreamSource::Torrent {
             torrent,
             file_id,
-        }));
+            cancel,
+        };
+        Ok(self.insert_stream(&path_name, mime, source).await)
+    }
 
-        let mut routes = self.routes.write().await;
-        routes.insert(to_path, id);
-        Ok(id)
+    /// Serve the file at `path` under `/stream/{path_name}`, like [`Rqstream::stream_file`]. The file is read anew
+    /// for every request, so it may still be growing
+    pub async fn stream_local(
+        &self,
+        path: impl Into<PathBuf>,
+        path_name: String,
+    ) -> anyhow::Result<StreamId> {
+        check_route(&path_name)?;
+        let path = path.into();
+        let meta = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("stat {}", path.display()))?;
+        if !meta.is_file() {
+            anyhow::bail!("{} is not a file", path.display());
+        }
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+        Ok(self
+            .insert_stream(&path_name, mime, StreamSource::Local(path))
+            .await)
     }
 
-    pub async fn stop_streaming(&self, id: StreamId) -> anyhow::Result<()> {
-        let file = self.streaming_files.write().await.remove(id.0);
+    /// Proxy `url` under `/stream/{path_name}`, like [`Rqstream::stream_file`]. Every upstream request carries
+    /// `headers`, for hosts that check e.g. the `Referer` or `User-Agent`.
+    ///
+    /// Ranges are passed through if upstream supports them, otherwise the body is served from the start only
+    pub async fn stream_http<K, V>(
+        &self,
+        url: &str,
+        headers: impl IntoIterator<Item = (K, V)>,
+        path_name: String,
+    ) -> anyhow::Result<StreamId>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        check_route(&path_name)?;
+        let headers = headers
+            .into_iter()
+            .map(|(k, v)| {
+                let (k, v) = (k.as_ref(), v.as_ref());
+                Ok((
+                    http::HeaderName::try_from(k).with_context(|| format!("header name {k:?}"))?,
+                    HeaderValue::try_from(v).with_context(|| format!("value of header {k}"))?,
+                ))
+            })
+            .collect::<anyhow::Result<HeaderMap>>()?;
+        let source = HttpSource::probe(self.http.clone(), url, headers)
+            .await
+            .with_context(|| format!("probe {url}"))?;
+        let mime = source
+            .content_type()
+            .unwrap_or_else(|| mime_guess::from_path(&path_name).first_or_octet_stream());
+        Ok(self
+            .insert_stream(&path_name, mime, StreamSource::Http(Box::new(source)))
+            .await)
+    }
 
-        self.routes.write().await.remove(&file.path);
+    async fn insert_stream(&self, path_name: &str, mime: Mime, source: StreamSource) -> StreamId {
+        // always routes before streams, like the handler
+        let mut routes = self.routes.write().await;
+        let mut streams = self.streaming_files.write().await;
+        let path = unique_route(&routes, path_name);
+        let id = StreamId(streams.insert(StreamingFile {
+            path: Arc::clone(&path),
+            mime,
+            source: Arc::new(source),
+        }));
+        routes.insert(path, id);
+        id
+    }
 
-        let mut rcs = self.torrent_refcounts.lock().await;
-        match rcs.entry(file.torrent.info_hash()) {
-            std::collections::hash_map::Entry::Occupied(mut occupied_entry) => {
-                let rc = occupied_entry.get_mut();
-                *rc = rc.saturating_sub(1);
-                if *rc == 0 {
-                    let _ = occupied_entry.remove_entry();
-                    self.session.delete(file.torrent.id().into(), false).await?;
-                }
+    /// Stop serving a stream. Once no lease or stream of a torrent is left, the torrent is removed along with its
+    /// data, after seeding as configured by [`RqstreamConfig::seed`]
+    pub async fn stop_streaming(self: &Arc<Self>, id: StreamId) -> anyhow::Result<()> {
+        let file = {
+            let mut routes = self.routes.write().await;
+            let mut streams = self.streaming_files.write().await;
+            let file = streams.try_remove(id.0).context("no such stream")?;
+            if routes.get(&file.path) == Some(&id) {
+                routes.remove(&file.path);
             }
-            std::collections::hash_map::Entry::Vacant(_) => {
-                self.session.delete(file.torrent.id().into(), false).await?;
+            file
+        };
+        match &*file.source {
+            StreamSource::Torrent {
+                torrent, cancel, ..
+            } => {
+                cancel.cancel();
+                self.release(torrent).await
             }
+            StreamSource::Local(_) | StreamSource::Http(_) => Ok(()),
         }
+    }
 
-        Ok(())
+    /// Pause `torrent` unless another stream, a lease or a download still needs it, returning whether it was paused.
+    ///
+    /// Meant for the stream of a player that stopped, so a paused stream sharing a torrent with one being watched
+    /// does not stall it
+    pub async fn pause(&self, torrent: &ManagedTorrent) -> anyhow::Result<bool> {
+        // held so nothing takes a reference in between
+        let refs = self.torrent_refs.lock().await;
+        let shared = refs
+            .get(&torrent.info_hash())
+            .is_none_or(|v| v.count > 1 || v.download.is_some());
+        if shared || torrent.is_paused() {
+            return Ok(false);
+        }
+        self.session.pause(torrent).await?;
+        Ok(true)
+    }
+
+    /// Files of `torrent` that are being streamed
+    async fn streamed_files(&self, torrent: &ManagedTorrent) -> HashSet<usize> {
+        self.streaming_files
+            .read()
+            .await
+            .iter()
+            .filter_map(|(_, file)| match &*file.source {
+                StreamSource::Torrent {
+                    torrent: other,
+                    file_id,
+                    ..
+                } if other.info_hash() == torrent.info_hash() => Some(*file_id),
+                _ => None,
+            })
+            .collect()
     }
+
+    /// Add `torrent` to the session, paused and without any files selected until one is streamed. The torrent is
+    /// removed along with its data once the returned lease and every stream of it are gone, and it has seeded as
+    /// configured by [`RqstreamConfig::seed`]. Fails if that would exceed [`RqstreamConfig::max_torrents`].
+    ///
+    /// List-only additions from [`Rqstream::get_info`] never enter the session, so they need no lease
     pub async fn add_managed<'a>(
-        &self,
+        self: &Arc<Self>,
+        torrent: AddTorrent<'a>,
+    ) -> anyhow::Result<TorrentLease> {
+        self.add_managed_with_peers(torrent, Vec::new()).await
+    }
+
+    /// Like [`Rqstream::add_managed`], also connecting to `peers` that are known some other way than through
+    /// trackers or the DHT
+    pub async fn add_managed_with_peers<'a>(
+        self: &Arc<Self>,
         torrent: AddTorrent<'a>,
-    ) -> anyhow::Result<Arc<ManagedTorrent>> {
-        let h = self
+        peers: Vec<SocketAddr>,
+    ) -> anyhow::Result<TorrentLease> {
+        let added = self
             .session
             .add_torrent(
                 torrent,
                 Some(AddTorrentOptions {
                     only_files: Some(Vec::new()),
                     paused: true,
+                    // reuse whatever an earlier session left in the cache dir
+                    overwrite: true,
+                    initial_peers: (!peers.is_empty()).then_some(peers),
                     ..Default::default()
                 }),
             )
+            .await?;
+        let (h, new) = match added {
+            AddTorrentResponse::Added(_, h) => (h, true),
+            AddTorrentResponse::AlreadyManaged(_, h) => (h, false),
+            AddTorrentResponse::ListOnly(_) => {
+                anyhow::bail!("torrent response did not produce handle")
+            }
+        };
+        self.lease(h, new, None).await
+    }
+
+    /// Download `file_id` of `torrent` into `output_folder` and keep it there, returning the path of the complete
+    /// file. Files larger than `max_len` bytes are refused before anything is downloaded.
+    ///
+    /// The data has to end up in `output_folder`, so this fails if the torrent is already in the session, e.g.
+    /// because it is being streamed. Streams started while it downloads share the download
+    pub async fn download<'a>(
+        self: &Arc<Self>,
+        torrent: AddTorrent<'a>,
+        file_id: usize,
+        output_folder: PathBuf,
+        max_len: Option<u64>,
+    ) -> anyhow::Result<PathBuf> {
+        self.download_with_peers(torrent, Vec::new(), file_id, output_folder, max_len)
+            .await
+    }
+
+    /// Like [`Rqstream::download`], also connecting to `peers` as in [`Rqstream::add_managed_with_peers`]
+    pub async fn download_with_peers<'a>(
+        self: &Arc<Self>,
+        torrent: AddTorrent<'a>,
+        peers: Vec<SocketAddr>,
+        file_id: usize,
+        output_folder: PathBuf,
+        max_len: Option<u64>,
+    ) -> anyhow::Result<PathBuf> {
+        tokio::fs::create_dir_all(&output_folder)
             .await
-            .and_then(|v| {
-                v.into_handle()
-                    .context("torrent response did not produce handle")
-            })?;
-        let mut rcs = self.torrent_refcounts.lock().await;
-        let rc = rcs.entry(h.info_hash()).or_default();
-        *rc += 1;
-        Ok(h)
+            .with_context(|| format!("create download dir {}", output_folder.display()))?;
+        let added = self
+            .session
+            .add_torrent(
+                torrent,
+                Some(AddTorrentOptions {
+                    only_files: Some(vec![file_id]),
+                    paused: true,
+                    // picks up where an interrupted download left off
+                    overwrite: true,
+                    output_folder: Some(output_folder.to_string_lossy().into_owned()),
+                    storage_factory: Some(FilesystemStorageFactory::default().boxed()),
+                    initial_peers: (!peers.is_empty()).then_some(peers),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        let AddTorrentResponse::Added(_, h) = added else {
+            anyhow::bail!("torrent is already in the session");
+        };
+        let lease = self.lease(h, true, Some(file_id)).await?;
+        let res = self
+            .finish_download(&lease, file_id, &output_folder, max_len)
+            .await;
+        if res.is_err()
+            && let Some(refs) = self.torrent_refs.lock().await.get_mut(&lease.info_hash())
+        {
+            // nothing worth keeping
+            refs.download = None;
+        }
+        lease.release().await?;
+        res
+    }
+
+    async fn finish_download(
+        &self,
+        lease: &TorrentLease,
+        file_id: usize,
+        output_folder: &std::path::Path,
+        max_len: Option<u64>,
+    ) -> anyhow::Result<PathBuf> {
+        let torrent = lease.torrent();
+        torrent.wait_until_initialized().await?;
+        let (len, relative_filename) = {
+            let meta = torrent.metadata.load();
+            let file = meta
+                .as_ref()
+                .context("get meta")?
+                .file_infos
+                .get(file_id)
+                .context("no such file")?;
+            (file.len, file.relative_filename.clone())
+        };
+        if let Some(max_len) = max_len
+            && len > max_len
+        {
+            anyhow::bail!("file is {len} bytes, more than the {max_len} allowed");
+        }
+        self.session.unpause(torrent).await?;
+        torrent.wait_until_completed().await?;
+        Ok(output_folder.join(relative_filename))
+    }
+
+    /// Count a reference to `torrent`, which was just added to the session (`new` if it was not in there before),
+    /// keeping `download` once it is removed
+    async fn lease(
+        self: &Arc<Self>,
+        torrent: Arc<ManagedTorrent>,
+        new: bool,
+        download: Option<usize>,
+    ) -> anyhow::Result<TorrentLease> {
+        let mut refs = self.torrent_refs.lock().await;
+        // releases delete while holding the lock, so this is only still gone if we raced the last one
+        if self.session.get(torrent.id().into()).is_none() {
+            anyhow::bail!("torrent was removed while it was being added");
+        }
+        if new
+            && let Some(max) = self.config.max_torrents
+            && !self.make_room(&mut refs, max).await?
+        {
+            // whatever an interrupted download left is still worth keeping
+            self.session
+                .delete(torrent.id().into(), download.is_none())
+                .await?;
+            anyhow::bail!("already {max} torrents in the session, none of them seeding");
+        }
+        let refs = refs.entry(torrent.info_hash()).or_default();
+        refs.count += 1;
+        if let Some(seeding) = refs.seeding.take() {
+            seeding.cancel.cancel();
+        }
+        if download.is_some() {
+            refs.download = download;
+        }
+        Ok(TorrentLease {
+            torrent,
+            rq: Some(Arc::clone(self)),
+        })
+    }
+
+    /// Remove seeding torrents, longest seeding first, until there are no more than `max` torrents in the
+    /// session. Returns whether that was enough
+    async fn make_room(
+        &self,
+        refs: &mut HashMap<Id20, TorrentRefs>,
+        max: usize,
+    ) -> anyhow::Result<bool> {
+        loop {
+            if self.session.with_torrents(|v| v.count()) <= max {
+                return Ok(true);
+            }
+            let Some(hash) = refs
+                .iter()
+                .filter_map(|(hash, v)| Some((hash, v.seeding.as_ref()?.since)))
+                .min_by_key(|(_, since)| *since)
+                .map(|(hash, _)| *hash)
+            else {
+                return Ok(false);
+            };
+            let removed = refs.remove(&hash).expect("just found");
+            let seeding = removed.seeding.expect("just found");
+            seeding.cancel.cancel();
+            self.session
+                .delete(seeding.torrent.id().into(), removed.download.is_none())
+                .await?;
+        }
+    }
+
+    /// Drop one reference to `torrent`, seeding it or removing it from the session if it was the last
+    async fn release(self: &Arc<Self>, torrent: &Arc<ManagedTorrent>) -> anyhow::Result<()> {
+        let mut refs = self.torrent_refs.lock().await;
+        let std::collections::hash_map::Entry::Occupied(mut rc) = refs.entry(torrent.info_hash())
+        else {
+            anyhow::bail!(
+                "torrent {} is not referenced",
+                torrent.info_hash().as_string()
+            );
+        };
+        rc.get_mut().count -= 1;
+        if rc.get().count > 0 {
+            return Ok(());
+        }
+        // nothing to give back if nothing was downloaded
+        if self.config.seed.enabled() && torrent.stats().progress_bytes > 0 {
+            match self.start_seeding(torrent).await {
+                Ok(cancel) => {
+                    rc.get_mut().seeding = Some(Seeding {
+                        torrent: Arc::clone(torrent),
+                        since: Instant::now(),
+                        cancel,
+                    });
+                    return Ok(());
+                }
+                Err(e) => log::warn!(
+                    "seeding {} failed, removing it instead: {e:#}",
+                    torrent.info_hash().as_string()
+                ),
+            }
+        }
+        let refs = rc.remove();
+        // a finished download is what the data was kept for
+        self.session
+            .delete(torrent.id().into(), refs.download.is_none())
+            .await?;
+        Ok(())
+    }
+
+    /// Seed what was downloaded of `torrent` without downloading any more, removing it once it has seeded as much
+    /// as [`RqstreamConfig::seed`] asks for. Called with `torrent_refs` locked
+    async fn start_seeding(
+        self: &Arc<Self>,
+        torrent: &Arc<ManagedTorrent>,
+    ) -> anyhow::Result<CancellationToken> {
+        self.session
+            .update_only_files(torrent, &HashSet::new())
+            .await?;
+        if torrent.is_paused() {
+            self.session.unpause(torrent).await?;
+        }
+        let cancel = CancellationToken::new();
+        let rq = Arc::clone(self);
+        let torrent = Arc::clone(torrent);
+        let cancelled = cancel.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = cancelled.cancelled() => return,
+                _ = rq.config.seed.wait(&torrent) => {}
+            }
+            let mut refs = rq.torrent_refs.lock().await;
+            // leases cancel under the lock, so it is still unreferenced if this was not
+            if cancelled.is_cancelled() {
+                return;
+            }
+            let Some(removed) = refs.remove(&torrent.info_hash()) else {
+                return;
+            };
+            if let Err(e) = rq
+                .session
+                .delete(torrent.id().into(), removed.download.is_none())
+                .await
+            {
+                log::error!(
+                    "removing seeded torrent {} failed: {e:#}",
+                    torrent.info_hash().as_string()
+                );
+            }
+        });
+        Ok(cancel)
     }
 }
 
+/// Keeps a torrent added with [`Rqstream::add_managed`] in the session, see there
+pub struct TorrentLease {
+    torrent: Arc<ManagedTorrent>,
+    /// `None` once released
+    rq: Option<Arc<Rqstream>>,
+}
+
+impl TorrentLease {
+    pub fn torrent(&self) -> &Arc<ManagedTorrent> {
+        &self.torrent
+    }
+    /// Give up the lease now rather than in the background once dropped, so errors can be handled
+    pub async fn release(mut self) -> anyhow::Result<()> {
+        match self.rq.take() {
+            Some(rq) => rq.release(&self.torrent).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::ops::Deref for TorrentLease {
+    type Target = Arc<ManagedTorrent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.torrent
+    }
+}
+
+impl Drop for TorrentLease {
+    fn drop(&mut self) {
+        let Some(rq) = self.rq.take() else {
+            return;
+        };
+        let torrent = Arc::clone(&self.torrent);
+        match tokio::runtime::Handle::try_current() {
+            Ok(rt) => {
+                rt.spawn(async move {
+                    if let Err(e) = rq.release(&torrent).await {
+                        log::warn!("releasing torrent failed: {e:#}");
+                    }
+                });
+            }
+            Err(_) => log::warn!(
+                "torrent lease dropped outside of a runtime, {} is kept",
+                torrent.info_hash().as_string()
+            ),
+        }
+    }
+}
+
+/// Unreserved characters only, so routes can be put into URLs as they are
+fn is_valid_route(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'))
+}
+
+fn check_route(name: &str) -> anyhow::Result<()> {
+    if !is_valid_route(name) {
+        anyhow::bail!("invalid route name {name:?}");
+    }
+    Ok(())
+}
+
+/// `name` if it is free, otherwise the first free `{stem}-{n}{extension}`, keeping the extension last for players
+/// that sniff it from the URL
+fn unique_route(routes: &HashMap<Arc<str>, StreamId>, name: &str) -> Arc<str> {
+    if !routes.contains_key(name) {
+        return name.into();
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(idx) if idx > 0 => name.split_at(idx),
+        _ => (name, ""),
+    };
+    (2..)
+        .map(|n| format!("{stem}-{n}{extension}"))
+        .find(|v| !routes.contains_key(v.as_str()))
+        .expect("some route to be free")
+        .into()
+}
+
 pub trait ResultExt<T> {
     fn anyhow_to_eyre(self) -> eyre::Result<T>;
 }
@@ -192,6 +827,15 @@ impl From<anyhow::Error> for Error {
     }
 }
 
+impl Error {
+    fn not_found(error: anyhow::Error) -> Self {
+        Error {
+            status: Some(StatusCode::NOT_FOUND),
+            error,
+        }
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         println!("error: {:#?}", self.error);
@@ -203,21 +847,59 @@ impl IntoResponse for Error {
 
 type AResult<T> = ::std::result::Result<T, Error>;
 
+async fn h_status(State(state): State<Arc<Rqstream>>) -> Json<Vec<StreamStats>> {
+    Json(state.all_stream_stats().await)
+}
+
+async fn h_stream_status(
+    State(state): State<Arc<Rqstream>>,
+    Path(id): Path<usize>,
+) -> AResult<Json<StreamStats>> {
+    state
+        .stream_stats(StreamId(id))
+        .await
+        .map(Json)
+        .ok_or_else(|| Error::not_found(anyhow::anyhow!("no stream {id}")))
+}
+
+/// Boundary between the parts of `multipart/byteranges` responses
+const BYTERANGES_BOUNDARY: &str = "rqstream-byteranges-boundary";
+
 async fn h_http_stream(
     State(state): State<Arc<Rqstream>>,
     Path(file_name): Path<String>,
+    method: Method,
     headers: http::HeaderMap,
-) -> AResult<impl IntoResponse> {
+) -> AResult<Response> {
     // mostly copied from rqbit's http api impl
-    let routes = state.routes.read().await;
-    let id = routes.get(&*file_name).context("no route")?;
-    let streaming = state.streaming_files.read().await;
-    let file = streaming.get(id.0).context("no file")?;
-    let cloned = Arc::clone(&file.torrent);
-    let mut stream = cloned.stream(file.file_id)?;
-    let mut status = StatusCode::OK;
+    let (stream_id, source, mime) = {
+        let routes = state.routes.read().await;
+        let id = *routes
+            .get(&*file_name)
+            .ok_or_else(|| Error::not_found(anyhow::anyhow!("no route {file_name}")))?;
+        let streaming = state.streaming_files.read().await;
+        let file = streaming.get(id.0).context("no file")?;
+        (id, Arc::clone(&file.source), file.mime.clone())
+    };
+    let desc = source.describe().await?;
+
     let mut output_headers = HeaderMap::new();
-    output_headers.insert("Accept-Ranges", HeaderValue::from_static("bytes"));
+    // without a length, neither ranges nor their responses can be formed
+    let seekable_len = desc.len.filter(|_| desc.ranges);
+    output_headers.insert(
+        http::header::ACCEPT_RANGES,
+        HeaderValue::from_static(if seekable_len.is_some() {
+            "bytes"
+        } else {
+            "none"
+        }),
+    );
+    if let Some(etag) = &desc.etag {
+        output_headers.insert(
+            http::header::ETAG,
+            HeaderValue::from_str(etag).context("bad etag")?,
+        );
+    }
 
     const DLNA_TRANSFER_MODE: &str = "transferMode.dlna.org";
     const DLNA_GET_CONTENT_FEATURES: &str = "getcontentFeatures.dlna.org";
@@ -240,167 +922,114 @@ async fn h_http_stream(
         );
     }
 
-    output_headers.insert(
-        http::header::CONTENT_TYPE,
-        HeaderValue::from_str(file.mime.essence_str()).expect("valid mime"),
-    );
+    let content_type = HeaderValue::from_str(mime.essence_str()).expect("valid mime");
 
-    let range_header = headers.get(http::header::RANGE);
+    let if_range = headers
+        .get(http::header::IF_RANGE)
+        .map(HeaderValue::as_bytes);
+    let ranges = match (headers.get(http::header::RANGE), seekable_len) {
+        (Some(range), Some(len)) if range::if_range_matches(if_range, desc.etag.as_deref()) => {
+            range::parse(range.as_bytes(), len)
+        }
+        _ => Ranges::Full,
+    };
+    // only ever used with ranges, which are only parsed against a known length
+    let total = seekable_len.unwrap_or_default();
+    // HEAD gets the same headers, without opening a stream (which would start prioritizing pieces)
+    let head = method == Method::HEAD;
+    let open = |range| source.open(&state, stream_id, range);
 
-    if let Some(range) = range_header {
-        let offset: Option<u64> = range
-            .to_str()
-            .ok()
-            .and_then(|s| s.strip_prefix("bytes="))
-            .and_then(|s| s.strip_suffix('-'))
-            .and_then(|s| s.parse().ok());
-        if let Some(offset) = offset {
-            status = StatusCode::PARTIAL_CONTENT;
-
-            stream
-                .seek(SeekFrom::Start(offset))
-                .await
-                .context("error seeking")?;
+    let stream_body = |reader: Box<dyn AsyncRead + Send + Unpin>| {
+        Body::from_stream(ReaderStream::with_capacity(reader, 65536))
+    };
 
+    let (status, content_length, body) = match ranges {
+        Ranges::Full => {
+            output_headers.insert(http::header::CONTENT_TYPE, content_type);
+            let body = if head {
+                Body::empty()
+            } else {
+                stream_body(open(desc.len.map(|len| 0..len)).await?)
+            };
+            (StatusCode::OK, desc.len, body)
+        }
+        Ranges::Unsatisfiable => {
             output_headers.insert(
-                http::header::CONTENT_LENGTH,
-                HeaderValue::from_str(&format!("{}", stream.len() - stream.position()))
-                    .context("bug")?,
+                http::header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{total}")).context("bug")?,
             );
+            (StatusCode::RANGE_NOT_SATISFIABLE, Some(0), Body::empty())
+        }
+        Ranges::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            output_headers.insert(http::header::CONTENT_TYPE, content_type);
             output_headers.insert(
                 http::header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{total}", range.start, range.end - 1))
+                    .context("bug")?,
+            );
+            let content_length = range.end - range.start;
+            let body = if head {
+                Body::empty()
+            } else {
+                stream_body(open(Some(range)).await?)
+            };
+            (StatusCode::PARTIAL_CONTENT, Some(content_length), body)
+        }
+        Ranges::Partial(ranges) => {
+            output_headers.insert(
+                http::header::CONTENT_TYPE,
                 HeaderValue::from_str(&format!(
-                    "bytes {}-{}/{}",
-                    stream.position(),
-                    stream.len().saturating_sub(1),
-                    stream.len()
+                    "multipart/byteranges; boundary={BYTERANGES_BOUNDARY}"
                 ))
                 .context("bug")?,
             );
+            let mut content_length = 0;
+            let mut parts: Vec<Box<dyn AsyncRead + Send + Unpin>> = Vec::new();
+            for range in ranges {
+                let part_header = format!(
+                    concat!(
+                        "\r\n--{}\r\n",
+                        "Content-Type: {}\r\n",
+                        "Content-Range: bytes {}-{}/{}\r\n\r\n"
+                    ),
+                    BYTERANGES_BOUNDARY,
+                    mime.essence_str(),
+                    range.start,
+                    range.end - 1,
+                    total
+                );
+                content_length += part_header.len() as u64 + (range.end - range.start);
+                if !head {
+                    parts.push(Box::new(Cursor::new(part_header)));
+                    parts.push(open(Some(range)).await?);
+                }
+            }
+            let trailer = format!("\r\n--{BYTERANGES_BOUNDARY}--\r\n");
+            content_length += trailer.len() as u64;
+
+            let body = if head {
+                Body::empty()
+            } else {
+                parts.push(Box::new(Cursor::new(trailer)));
+                let reader = parts
+                    .into_iter()
+                    .reduce(|a, b| Box::new(a.chain(b)))
+                    .expect("at least the trailer");
+                stream_body(reader)
+            };
+            (StatusCode::PARTIAL_CONTENT, Some(content_length), body)
         }
-    } else {
+    };
+    if let Some(content_length) = content_length {
         output_headers.insert(
             http::header::CONTENT_LENGTH,
-            HeaderValue::from_str(&format!("{}", stream.len())).context("bug")?,
+            HeaderValue::from_str(&content_length.to_string()).context("bug")?,
         );
     }
 
-    let s = ReaderStream::with_capacity(stream, 65536);
-    Ok((status, (output_headers, axum::body::Body::from_stream(s))))
+    Ok((status, output_headers, body).into_response())
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub struct StreamId(usize);
-
-#[derive(Clone, Copy)]
-pub struct InMemStorageFactory;
-
-pub struct InMemStorage {
-    files: Vec<std::sync::RwLock<Vec<u8>>>,
-}
-
-impl StorageFactory for InMemStorageFactory {
-    type Storage = InMemStorage;
-
-    fn create(
-        &self,
-        _shared: &librqbit::ManagedTorrentShared,
-        metadata: &librqbit::TorrentMetadata,
-    ) -> anyhow::Result<Self::Storage> {
-        // TODO delegate to rqbit's disk TorrentStorage for large files
-        Ok(InMemStorage {
-            files: repeat_with(|| ::std::sync::RwLock::new(Vec::new()))
-                .take(metadata.file_infos.len())
-                .collect(),
-        })
-    }
-
-    fn clone_box(&self) -> librqbit::storage::BoxStorageFactory {
-        self.boxed()
-    }
-}
-
-mod storage_impl {
-    use crate::InMemStorage;
-    use anyhow::{Context, anyhow};
-    use librqbit::storage::TorrentStorage;
-    use std::{mem::take, sync::RwLock};
-
-    impl TorrentStorage for InMemStorage {
-        fn init(
-            &mut self,
-            _shared: &librqbit::ManagedTorrentShared,
-            _metadata: &librqbit::TorrentMetadata,
-        ) -> anyhow::Result<()> {
-            Ok(())
-        }
-
-        fn pread_exact(&self, file_id: usize, offset: u64, buf: &mut [u8]) -> anyhow::Result<()> {
-            let s = self
-                .files
-                .get(file_id)
-                .context("file was none")?
-                .read()
-                .map_err(|_| anyhow!("bug"))?;
-            let offset = offset as usize;
-            let remaining = s.len() - offset;
-            if buf.len() > remaining {
-                return Err(anyhow!("out of bounds read of file"));
-            }
-            buf.copy_from_slice(&s[offset..(offset + buf.len())]);
-            Ok(())
-        }
-
-        fn pwrite_all(&self, file_id: usize, offset: u64, buf: &[u8]) -> anyhow::Result<()> {
-            let mut s = self
-                .files
-                .get(file_id)
-                .context("file was none")?
-                .write()
-                .map_err(|_| anyhow!("bug"))?;
-            let offset = offset as usize;
-            let len = s.len().max(offset + buf.len());
-            s.resize(len, 0);
-            s[offset..(offset + buf.len())].copy_from_slice(buf);
-            Ok(())
-        }
-
-        fn remove_file(&self, file_id: usize, _filename: &std::path::Path) -> anyhow::Result<()> {
-            // we can't remove outright but we can at least dealloc the backing buffer
-            let mut s = self
-                .files
-                .get(file_id)
-                .context("file was none")?
-                .write()
-                .map_err(|_| anyhow!("bug"))?;
-            // explicit drop
-            drop(take(&mut *s));
-            Ok(())
-        }
-
-        fn remove_directory_if_empty(&self, _path: &std::path::Path) -> anyhow::Result<()> {
-            // unsupported
-            Ok(())
-        }
-
-        fn ensure_file_length(&self, file_id: usize, length: u64) -> anyhow::Result<()> {
-            let mut s = self
-                .files
-                .get(file_id)
-                .context("file was none")?
-                .write()
-                .map_err(|_| anyhow!("bug"))?;
-            s.resize(length as usize, 0);
-            Ok(())
-        }
-
-        fn take(&self) -> anyhow::Result<Box<dyn TorrentStorage>> {
-            let mut files = Vec::with_capacity(self.files.len());
-            for file in self.files.iter().map(|v| v.write()) {
-                let mut file = file.map_err(|_| anyhow!("bug"))?;
-                files.push(RwLock::new(take(&mut *file)));
-            }
-            Ok(Box::new(Self { files }) as Box<dyn TorrentStorage>)
-        }
-    }
-}
@@ -0,0 +1,182 @@
+//! `Range` request header handling, as specified in RFC 7233
+use std::ops::Range;
+
+/// Past this many ranges (after merging) a request is answered with the whole file instead, see RFC 7233 section 6.1
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Ranges {
+    /// No usable range was requested, respond with the whole representation
+    Full,
+    /// Respond with these non-overlapping byte ranges, in ascending order
+    Partial(Vec<Range<u64>>),
+    /// Syntactically valid, but none of the ranges overlap the representation
+    Unsatisfiable,
+}
+
+/// Parse the value of a `Range` header for a representation of `len` bytes.
+///
+/// Malformed headers and units other than `bytes` are ignored, as the RFC requires
+pub(crate) fn parse(header: &[u8], len: u64) -> Ranges {
+    let Some(specs) = std::str::from_utf8(header)
+        .ok()
+        .and_then(|v| v.trim().split_once('='))
+        .filter(|(unit, _)| unit.trim().eq_ignore_ascii_case("bytes"))
+        .map(|(_, specs)| specs)
+    else {
+        return Ranges::Full;
+    };
+
+    let mut ranges = Vec::new();
+    let mut any = false;
+    // empty list elements are allowed
+    for spec in specs.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let Some((first, last)) = spec.split_once('-') else {
+            return Ranges::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let range = match (first.is_empty(), last.is_empty()) {
+            // suffix-byte-range-spec, the final `last` bytes
+            (true, false) => {
+                let Some(suffix) = parse_int(last) else {
+                    return Ranges::Full;
+                };
+                len.saturating_sub(suffix)..len
+            }
+            (false, last_empty) => {
+                let Some(first) = parse_int(first) else {
+                    return Ranges::Full;
+                };
+                let end = if last_empty {
+                    len
+                } else {
+                    let Some(last) = parse_int(last) else {
+                        return Ranges::Full;
+                    };
+                    if last < first {
+                        return Ranges::Full;
+                    }
+                    last.saturating_add(1).min(len)
+                };
+                first..end
+            }
+            (true, true) => return Ranges::Full,
+        };
+        any = true;
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+    if !any {
+        return Ranges::Full;
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|v| v.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(prev) if range.start <= prev.end => prev.end = prev.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    if merged.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+    Ranges::Partial(merged)
+}
+
+/// `1*DIGIT`. Unlike [`str::parse`] this rejects signs
+fn parse_int(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Whether a request's `If-Range` precondition holds for a representation with the strong validator `etag`, if it
+/// has one.
+///
+/// We have no modification dates, so a date never matches and the whole file is sent instead
+pub(crate) fn if_range_matches(if_range: Option<&[u8]>, etag: Option<&str>) -> bool {
+    if_range.is_none_or(|v| etag.is_some_and(|etag| v == etag.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Ranges, if_range_matches, parse};
+
+    fn partial(ranges: &[(u64, u64)]) -> Ranges {
+        Ranges::Partial(ranges.iter().map(|&(a, b)| a..b).collect())
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse(b"bytes=0-", 100), partial(&[(0, 100)]));
+        assert_eq!(parse(b"bytes=10-19", 100), partial(&[(10, 20)]));
+        assert_eq!(parse(b"bytes=90-200", 100), partial(&[(90, 100)]));
+        assert_eq!(parse(b"bytes=-10", 100), partial(&[(90, 100)]));
+        assert_eq!(parse(b"bytes=-500", 100), partial(&[(0, 100)]));
+        assert_eq!(parse(b"Bytes = 5-5", 100), partial(&[(5, 6)]));
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        assert_eq!(
+            parse(b"bytes=50-59, 0-9,,-5", 100),
+            partial(&[(0, 10), (50, 60), (95, 100)])
+        );
+        // overlapping and adjacent ranges are merged
+        assert_eq!(parse(b"bytes=0-9,5-19,20-29", 100), partial(&[(0, 30)]));
+        // unsatisfiable ones are dropped as long as one is left
+        assert_eq!(parse(b"bytes=200-300,0-0", 100), partial(&[(0, 1)]));
+
+        let many = (0..20)
+            .map(|v| format!("{}-{}", v * 2, v * 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(parse(format!("bytes={many}").as_bytes(), 100), Ranges::Full);
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(parse(b"bytes=100-", 100), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=150-160,200-", 100), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=-0", 100), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=0-", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignored() {
+        for header in [
+            &b"items=0-5"[..],
+            b"bytes=",
+            b"bytes=5",
+            b"bytes=-",
+            b"bytes=10-5",
+            b"bytes=+1-5",
+            b"bytes=a-b",
+            b"bytes=0-5,x",
+            b"\xff",
+        ] {
+            assert_eq!(parse(header, 100), Ranges::Full, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn if_range() {
+        let etag = Some("\"abc-0\"");
+        assert!(if_range_matches(None, etag));
+        assert!(if_range_matches(Some(b"\"abc-0\""), etag));
+        assert!(!if_range_matches(Some(b"\"abc-1\""), etag));
+        assert!(!if_range_matches(Some(b"W/\"abc-0\""), etag));
+        assert!(!if_range_matches(
+            Some(b"Wed, 21 Oct 2015 07:28:00 GMT"),
+            etag
+        ));
+        assert!(if_range_matches(None, None));
+        assert!(!if_range_matches(Some(b"\"abc-0\""), None));
+    }
+}
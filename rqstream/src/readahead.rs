@@ -0,0 +1,267 @@
+//! Prefetching ahead of where streams are being read, so playback after a seek starts quickly
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use librqbit::{ManagedTorrent, dht::Id20};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, ReadBuf},
+    sync::Notify,
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::StreamId;
+
+/// Largest read issued while prefetching, the data itself is thrown away
+const PREFETCH_CHUNK: u64 = 256 * 1024;
+
+/// How much of a streamed file to fetch before the player asks for it
+#[derive(Debug, Clone, Copy)]
+pub struct ReadAheadConfig {
+    /// prefetched after the position of each active reader
+    pub window: ReadAheadWindow,
+    /// bytes at the end of each file to fetch as soon as it starts streaming. MKV and MP4 keep
+    /// their seek index there, so players read it before anything else
+    pub tail: u64,
+}
+
+impl Default for ReadAheadConfig {
+    fn default() -> Self {
+        Self {
+            window: ReadAheadWindow::Seconds {
+                seconds: 60,
+                runtime: Duration::from_secs(24 * 60),
+            },
+            tail: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReadAheadWindow {
+    /// A fixed number of bytes
+    Bytes(u64),
+    /// This many seconds of playback, estimating the bitrate as if each file played for `runtime`
+    Seconds { seconds: u64, runtime: Duration },
+}
+
+impl ReadAheadWindow {
+    /// The window in bytes, for a file of `len` bytes
+    pub fn bytes(&self, len: u64) -> u64 {
+        match *self {
+            ReadAheadWindow::Bytes(v) => v,
+            ReadAheadWindow::Seconds { seconds, runtime } => {
+                let bytes_per_sec = len / runtime.as_secs().max(1);
+                bytes_per_sec.saturating_mul(seconds)
+            }
+        }
+    }
+}
+
+/// Readers of streamed files that are currently being served
+#[derive(Default)]
+pub(crate) struct Readers {
+    active: Mutex<Vec<Weak<Reader>>>,
+}
+
+/// Where a response body is within its file
+pub(crate) struct Reader {
+    stream: StreamId,
+    info_hash: Id20,
+    file_id: usize,
+    pos: AtomicU64,
+    /// everything from `pos` up to here is downloaded
+    prefetched: AtomicU64,
+    moved: Notify,
+}
+
+impl Reader {
+    fn position(&self) -> u64 {
+        self.pos.load(Ordering::Acquire)
+    }
+    /// The downloaded range starting at the reader's position
+    fn buffered(&self) -> Range<u64> {
+        let pos = self.position();
+        pos..self.prefetched.load(Ordering::Acquire).max(pos)
+    }
+    fn advance(&self, bytes: u64) {
+        self.pos.fetch_add(bytes, Ordering::AcqRel);
+        self.moved.notify_one();
+    }
+}
+
+impl Readers {
+    fn active(&self) -> Vec<Arc<Reader>> {
+        let mut active = self.active.lock().expect("not poisoned");
+        active.retain(|v| v.strong_count() > 0);
+        active.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// The position of the reader furthest behind in a file, if it is being read
+    pub(crate) fn min_position(&self, info_hash: Id20, file_id: usize) -> Option<u64> {
+        self.active()
+            .iter()
+            .filter(|v| v.info_hash == info_hash && v.file_id == file_id)
+            .map(|v| v.position())
+            .min()
+    }
+
+    /// The downloaded range ahead of the newest reader of `stream`, if it is being read
+    pub(crate) fn buffered(&self, stream: StreamId) -> Option<Range<u64>> {
+        self.active()
+            .iter()
+            .rfind(|v| v.stream == stream)
+            .map(|v| v.buffered())
+    }
+
+    /// Track `inner`, which reads `range` of `file_id` for `stream`, and prefetch up to `window` bytes ahead of it until it is dropped
+    pub(crate) fn track<R>(
+        &self,
+        stream: StreamId,
+        torrent: &Arc<ManagedTorrent>,
+        file_id: usize,
+        range: Range<u64>,
+        window: u64,
+        inner: R,
+    ) -> TrackedReader<R> {
+        let reader = Arc::new(Reader {
+            stream,
+            info_hash: torrent.info_hash(),
+            file_id,
+            pos: AtomicU64::new(range.start),
+            prefetched: AtomicU64::new(range.start),
+            moved: Notify::new(),
+        });
+        self.active
+            .lock()
+            .expect("not poisoned")
+            .push(Arc::downgrade(&reader));
+
+        let cancel = CancellationToken::new();
+        if window > 0 {
+            let torrent = Arc::clone(torrent);
+            let reader = Arc::clone(&reader);
+            let cancelled = cancel.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = cancelled.cancelled() => {}
+                    res = prefetch(torrent, file_id, &reader, range.end, window) => {
+                        if let Err(e) = res {
+                            log::debug!("prefetch for {stream:?} failed: {e:#}");
+                        }
+                    }
+                }
+            });
+        }
+        TrackedReader {
+            inner,
+            reader,
+            _prefetch: cancel.drop_guard(),
+        }
+    }
+}
+
+/// Keep up to `window` bytes ahead of `reader` downloaded, stopping at `end`
+async fn prefetch(
+    torrent: Arc<ManagedTorrent>,
+    file_id: usize,
+    reader: &Reader,
+    end: u64,
+    window: u64,
+) -> anyhow::Result<()> {
+    // reading through a stream of our own makes librqbit prioritise the pieces it is waiting on
+    let mut stream = torrent.stream(file_id)?;
+    let mut pos = 0;
+    let mut buf = vec![0; PREFETCH_CHUNK as usize];
+    loop {
+        let reader_pos = reader.position();
+        if pos < reader_pos {
+            // skip what the reader already has
+            pos = stream.seek(SeekFrom::Start(reader_pos)).await?;
+        }
+        if pos >= end {
+            return Ok(());
+        }
+        let target = reader_pos.saturating_add(window).min(end);
+        if pos >= target {
+            reader.moved.notified().await;
+            continue;
+        }
+        let n = (target - pos).min(PREFETCH_CHUNK) as usize;
+        let read = stream.read(&mut buf[..n]).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        pos += read as u64;
+        reader.prefetched.store(pos, Ordering::Release);
+    }
+}
+
+/// Fetch the last `tail` bytes of a file of `len` bytes
+pub(crate) async fn prefetch_tail(
+    torrent: Arc<ManagedTorrent>,
+    file_id: usize,
+    len: u64,
+    tail: u64,
+) -> anyhow::Result<()> {
+    let mut stream = torrent.stream(file_id)?;
+    stream
+        .seek(SeekFrom::Start(len.saturating_sub(tail)))
+        .await?;
+    tokio::io::copy(&mut stream, &mut tokio::io::sink()).await?;
+    Ok(())
+}
+
+/// A response body reader whose position is tracked in [`Readers`]
+pub(crate) struct TrackedReader<R> {
+    inner: R,
+    reader: Arc<Reader>,
+    _prefetch: DropGuard,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TrackedReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - filled;
+        if read > 0 {
+            self.reader.advance(read as u64);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ReadAheadWindow;
+
+    #[test]
+    fn window_bytes() {
+        assert_eq!(ReadAheadWindow::Bytes(1234).bytes(1 << 30), 1234);
+        let seconds = ReadAheadWindow::Seconds {
+            seconds: 60,
+            runtime: Duration::from_secs(1000),
+        };
+        // 1 MB/s
+        assert_eq!(seconds.bytes(1_000_000_000), 60_000_000);
+        let instant = ReadAheadWindow::Seconds {
+            seconds: 10,
+            runtime: Duration::ZERO,
+        };
+        assert_eq!(instant.bytes(100), 1000);
+    }
+}
@@ -0,0 +1,229 @@
+//! What a stream serves: a file of a torrent, a local file or an upstream HTTP URL
+use std::{io::SeekFrom, ops::Range, path::PathBuf, sync::Arc, time::UNIX_EPOCH};
+
+use anyhow::Context;
+use futures::TryStreamExt;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use librqbit::ManagedTorrent;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_util::{io::StreamReader, sync::CancellationToken};
+
+use crate::{Rqstream, StreamId};
+
+pub(crate) type Reader = Box<dyn AsyncRead + Send + Unpin>;
+
+pub(crate) enum StreamSource {
+    Torrent {
+        torrent: Arc<ManagedTorrent>,
+        file_id: usize,
+        /// stops the tail prefetch once the stream is stopped
+        cancel: CancellationToken,
+    },
+    Local(PathBuf),
+    Http(Box<HttpSource>),
+}
+
+/// An upstream URL, fetched with the headers its host requires
+pub(crate) struct HttpSource {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    headers: HeaderMap,
+    /// length and validator as reported when the stream was started
+    len: Option<u64>,
+    etag: Option<String>,
+    ranges: bool,
+    content_type: Option<HeaderValue>,
+}
+
+/// What the handler needs to know about a source before serving it
+pub(crate) struct Description {
+    /// `None` if unknown, in which case the body is passed through as it is
+    pub len: Option<u64>,
+    /// strong validator, for `If-Range`
+    pub etag: Option<String>,
+    /// whether the source can be read from anywhere but the start
+    pub ranges: bool,
+}
+
+impl HttpSource {
+    /// Ask `url` for its first byte, to learn whether it supports ranges and how long it is
+    pub(crate) async fn probe(
+        client: reqwest::Client,
+        url: &str,
+        headers: HeaderMap,
+    ) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(url).context("parse upstream url")?;
+        let resp = client
+            .get(url.clone())
+            .headers(headers.clone())
+            .header(header::RANGE, "bytes=0-0")
+            .send()
+            .await?
+            .error_for_status()?;
+        let resp_headers = resp.headers();
+        let (len, ranges) = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => (
+                resp_headers
+                    .get(header::CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit_once('/'))
+                    .and_then(|(_, len)| len.parse().ok()),
+                true,
+            ),
+            _ => (resp.content_length(), false),
+        };
+        let etag = resp_headers
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.starts_with("W/"))
+            .map(str::to_string);
+        let content_type = resp_headers.get(header::CONTENT_TYPE).cloned();
+        Ok(Self {
+            client,
+            url,
+            headers,
+            len,
+            etag,
+            // without a length there is nothing to check ranges against
+            ranges: ranges && len.is_some(),
+            content_type,
+        })
+    }
+
+    /// The upstream media type, unless it is too generic to be useful
+    pub(crate) fn content_type(&self) -> Option<mime_guess::Mime> {
+        self.content_type
+            .as_ref()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<mime_guess::Mime>().ok())
+            .filter(|v| *v != mime_guess::mime::APPLICATION_OCTET_STREAM)
+    }
+
+    async fn open(&self, range: Option<Range<u64>>) -> anyhow::Result<Reader> {
+        let mut req = self
+            .client
+            .get(self.url.clone())
+            .headers(self.headers.clone());
+        if let Some(range) = range.as_ref().filter(|_| self.ranges) {
+            req = req.header(
+                header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            );
+        }
+        let resp = req.send().await?.error_for_status()?;
+        let partial = resp.status() == StatusCode::PARTIAL_CONTENT;
+        let mut reader: Reader = Box::new(StreamReader::new(
+            resp.bytes_stream().map_err(std::io::Error::other),
+        ));
+        if let Some(range) = range {
+            if !partial && range.start > 0 {
+                // upstream does not do ranges (anymore), skip to it
+                tokio::io::copy(&mut (&mut reader).take(range.start), &mut tokio::io::sink())
+                    .await?;
+            }
+            reader = Box::new(reader.take(range.end - range.start));
+        }
+        Ok(reader)
+    }
+}
+
+impl StreamSource {
+    pub(crate) async fn describe(&self) -> anyhow::Result<Description> {
+        Ok(match self {
+            StreamSource::Torrent {
+                torrent, file_id, ..
+            } => {
+                let len = torrent
+                    .metadata
+                    .load()
+                    .as_ref()
+                    .context("get meta")?
+                    .file_infos
+                    .get(*file_id)
+                    .context("no file info")?
+                    .len;
+                Description {
+                    len: Some(len),
+                    // a torrent's content never changes, so this is a strong validator
+                    etag: Some(format!("\"{}-{file_id}\"", torrent.info_hash().as_string())),
+                    ranges: true,
+                }
+            }
+            StreamSource::Local(path) => {
+                let meta = tokio::fs::metadata(path)
+                    .await
+                    .with_context(|| format!("stat {}", path.display()))?;
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|v| v.duration_since(UNIX_EPOCH).ok());
+                Description {
+                    len: Some(meta.len()),
+                    // rewriting a file within the same timestamp granularity is not worth worrying about
+                    etag: modified.map(|v| format!("\"{:x}-{:x}\"", meta.len(), v.as_nanos())),
+                    ranges: true,
+                }
+            }
+            StreamSource::Http(source) => Description {
+                len: source.len,
+                etag: source.etag.clone(),
+                ranges: source.ranges,
+            },
+        })
+    }
+
+    /// Read `range` of the source, or all of it if `None`
+    pub(crate) async fn open(
+        &self,
+        rq: &Rqstream,
+        id: StreamId,
+        range: Option<Range<u64>>,
+    ) -> anyhow::Result<Reader> {
+        match self {
+            StreamSource::Torrent {
+                torrent, file_id, ..
+            } => {
+                let range = range.context("torrent files have a length")?;
+                let mut stream = torrent.stream(*file_id)?;
+                if range.start != 0 {
+                    stream
+                        .seek(SeekFrom::Start(range.start))
+                        .await
+                        .context("error seeking")?;
+                }
+                let len = stream.take(range.end - range.start);
+                let window = rq
+                    .read_ahead
+                    .window
+                    .bytes(torrent_file_len(torrent, *file_id));
+                Ok(Box::new(
+                    rq.readers.track(id, torrent, *file_id, range, window, len),
+                ))
+            }
+            StreamSource::Local(path) => {
+                let mut file = tokio::fs::File::open(path)
+                    .await
+                    .with_context(|| format!("open {}", path.display()))?;
+                match range {
+                    Some(range) => {
+                        if range.start != 0 {
+                            file.seek(SeekFrom::Start(range.start)).await?;
+                        }
+                        Ok(Box::new(file.take(range.end - range.start)))
+                    }
+                    None => Ok(Box::new(file)),
+                }
+            }
+            StreamSource::Http(source) => source.open(range).await,
+        }
+    }
+}
+
+fn torrent_file_len(torrent: &ManagedTorrent, file_id: usize) -> u64 {
+    torrent
+        .metadata
+        .load()
+        .as_ref()
+        .and_then(|v| v.file_infos.get(file_id))
+        .map_or(0, |v| v.len)
+}
@@ -0,0 +1,96 @@
+//! Download progress of individual streams
+use std::{ops::Range, sync::Arc, time::Duration};
+
+use librqbit::ManagedTorrent;
+use serde::{Serialize, Serializer};
+
+use crate::StreamId;
+
+/// Progress of the file behind a stream, see [`crate::Rqstream::stream_stats`]
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamStats {
+    pub id: StreamId,
+    /// route the stream is served under
+    pub path: Arc<str>,
+    /// bytes of the file that are downloaded and verified
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    /// peers of the whole torrent that are currently connected
+    pub peers: usize,
+    /// of the whole torrent, in bytes per second
+    pub download_speed: f64,
+    pub upload_speed: f64,
+    /// downloaded range starting at the position of the newest reader, if the stream is being read
+    pub buffered: Option<Range<u64>>,
+    /// until the file is complete at the current download speed, if it is downloading at all
+    #[serde(serialize_with = "serialize_secs")]
+    pub eta: Option<Duration>,
+}
+
+impl StreamStats {
+    pub(crate) fn collect(
+        id: StreamId,
+        path: Arc<str>,
+        torrent: &ManagedTorrent,
+        file_id: usize,
+        total_bytes: u64,
+        buffered: Option<Range<u64>>,
+    ) -> Self {
+        const MIB: f64 = 1024.0 * 1024.0;
+        let stats = torrent.stats();
+        let downloaded_bytes = stats
+            .file_progress
+            .get(file_id)
+            .copied()
+            .unwrap_or(0)
+            .min(total_bytes);
+        let (peers, download_speed, upload_speed) = match &stats.live {
+            Some(live) => (
+                live.snapshot.peer_stats.live,
+                live.download_speed.mbps * MIB,
+                live.upload_speed.mbps * MIB,
+            ),
+            None => (0, 0.0, 0.0),
+        };
+        Self {
+            id,
+            path,
+            downloaded_bytes,
+            total_bytes,
+            peers,
+            download_speed,
+            upload_speed,
+            buffered,
+            eta: eta(total_bytes - downloaded_bytes, download_speed),
+        }
+    }
+}
+
+fn eta(remaining: u64, bytes_per_sec: f64) -> Option<Duration> {
+    if remaining == 0 {
+        return Some(Duration::ZERO);
+    }
+    if bytes_per_sec <= 0.0 {
+        return None;
+    }
+    Duration::try_from_secs_f64(remaining as f64 / bytes_per_sec).ok()
+}
+
+/// As fractional seconds, which is what JSON consumers expect
+fn serialize_secs<S: Serializer>(v: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    v.map(|v| v.as_secs_f64()).serialize(s)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::eta;
+
+    #[test]
+    fn eta_from_speed() {
+        assert_eq!(eta(0, 0.0), Some(Duration::ZERO));
+        assert_eq!(eta(100, 0.0), None);
+        assert_eq!(eta(100, 50.0), Some(Duration::from_secs(2)));
+    }
+}
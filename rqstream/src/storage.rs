@@ -0,0 +1,871 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, RwLock, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Context;
+use librqbit::{
+    dht::Id20,
+    storage::{
+        BoxStorageFactory, StorageFactory, StorageFactoryExt, TorrentStorage,
+        filesystem::FilesystemStorageFactory,
+    },
+};
+
+use crate::readahead::Readers;
+
+/// Where the data of streamed torrents is kept
+#[derive(Debug, Clone)]
+pub enum StorageConfig {
+    /// Pieces that have been downloaded, in memory. Pieces evicted to stay under the ceiling are moved to a
+    /// temporary file, as the torrent still has them as far as peers and seeking back are concerned
+    InMemory(MemoryLimit),
+    /// Files under `cache_dir`
+    Disk { cache_dir: PathBuf },
+    /// Files up to `max_in_memory_file` bytes in memory, as long as all in-memory files together stay under
+    /// the ceiling of `memory`. Everything else goes to `cache_dir`
+    Hybrid {
+        cache_dir: PathBuf,
+        max_in_memory_file: u64,
+        memory: MemoryLimit,
+    },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::InMemory(MemoryLimit::default())
+    }
+}
+
+/// Bounds for torrent data kept in memory
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLimit {
+    /// Bytes all in-memory pieces together may take up. Once reached, pieces far behind where their file is
+    /// being read are moved out of memory to make room. The ceiling is exceeded rather than evicting pieces still ahead of a reader
+    pub ceiling: u64,
+    /// Pieces within this many bytes behind the read position are kept for seeking back
+    pub keep_behind: u64,
+}
+
+impl Default for MemoryLimit {
+    fn default() -> Self {
+        Self {
+            ceiling: 1024 * 1024 * 1024,
+            keep_behind: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// [`StorageConfig::Hybrid`] with limits suitable for single episodes
+    pub fn hybrid(cache_dir: impl Into<PathBuf>) -> Self {
+        Self::Hybrid {
+            cache_dir: cache_dir.into(),
+            max_in_memory_file: 512 * 1024 * 1024,
+            memory: MemoryLimit::default(),
+        }
+    }
+    /// Directory files on disk are placed under, if any
+    pub fn cache_dir(&self) -> Option<&PathBuf> {
+        match self {
+            StorageConfig::InMemory(_) => None,
+            StorageConfig::Disk { cache_dir } | StorageConfig::Hybrid { cache_dir, .. } => {
+                Some(cache_dir)
+            }
+        }
+    }
+    /// The factory for this config, evicting in-memory data based on where `readers` are
+    pub(crate) fn factory(&self, readers: &Arc<Readers>) -> BoxStorageFactory {
+        match *self {
+            StorageConfig::InMemory(limit) => InMemStorageFactory::new(limit)
+                .with_readers(readers)
+                .boxed(),
+            StorageConfig::Disk { .. } => FilesystemStorageFactory::default().boxed(),
+            StorageConfig::Hybrid {
+                max_in_memory_file,
+                memory,
+                ..
+            } => HybridStorageFactory {
+                max_in_memory_file,
+                budget: Arc::new(MemoryBudget::new(memory.ceiling)),
+                mem: InMemStorageFactory::new(memory).with_readers(readers),
+            }
+            .boxed(),
+        }
+    }
+}
+
+/// Bytes of torrent data held in memory across every storage created by one factory
+struct MemoryBudget {
+    limit: u64,
+    used: AtomicU64,
+    /// storages that can give memory back when the limit is reached
+    storages: Mutex<Vec<Weak<SparseFiles>>>,
+}
+
+impl MemoryBudget {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+            storages: Mutex::new(Vec::new()),
+        }
+    }
+    fn try_reserve(&self, bytes: u64) -> bool {
+        let mut used = self.used.load(Ordering::Acquire);
+        loop {
+            let Some(next) = used.checked_add(bytes).filter(|v| *v <= self.limit) else {
+                return false;
+            };
+            match self
+                .used
+                .compare_exchange_weak(used, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(v) => used = v,
+            }
+        }
+    }
+    /// Reserve `bytes`, evicting from registered storages if needed. Goes over the limit if not enough can be evicted
+    fn reserve_evicting(&self, bytes: u64, keep_behind: u64) {
+        if self.try_reserve(bytes) {
+            return;
+        }
+        let storages: Vec<_> = {
+            let mut storages = self.storages.lock().expect("not poisoned");
+            storages.retain(|v| v.strong_count() > 0);
+            storages.iter().filter_map(Weak::upgrade).collect()
+        };
+        let mut freed = 0;
+        for storage in storages {
+            if freed >= bytes {
+                break;
+            }
+            freed += storage.evict_behind(keep_behind, bytes - freed);
+        }
+        if !self.try_reserve(bytes) {
+            self.reserve_unchecked(bytes);
+        }
+    }
+    fn reserve_unchecked(&self, bytes: u64) {
+        self.used.fetch_add(bytes, Ordering::AcqRel);
+    }
+    fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+    fn register(&self, storage: &Arc<SparseFiles>) {
+        self.storages
+            .lock()
+            .expect("not poisoned")
+            .push(Arc::downgrade(storage));
+    }
+}
+
+/// Keeps the pieces of each file that have been written in memory, evicting ones far behind the reader
+/// once the [`MemoryLimit`] is reached
+#[derive(Clone)]
+pub struct InMemStorageFactory {
+    limit: MemoryLimit,
+    budget: Arc<MemoryBudget>,
+    readers: Arc<Readers>,
+}
+
+impl InMemStorageFactory {
+    pub fn new(limit: MemoryLimit) -> Self {
+        Self {
+            limit,
+            budget: Arc::new(MemoryBudget::new(limit.ceiling)),
+            readers: Default::default(),
+        }
+    }
+    fn with_readers(self, readers: &Arc<Readers>) -> Self {
+        Self {
+            readers: Arc::clone(readers),
+            ..self
+        }
+    }
+}
+
+pub struct InMemStorage {
+    files: Arc<SparseFiles>,
+}
+
+struct SparseFiles {
+    info_hash: Id20,
+    files: Vec<SparseFile>,
+    piece_length: u64,
+    keep_behind: u64,
+    budget: Arc<MemoryBudget>,
+    readers: Arc<Readers>,
+    spill: Mutex<Spill>,
+}
+
+/// Pieces evicted from memory. librqbit considers them downloaded and never fetches them again, so they have to
+/// stay readable for seeking back and for peers
+#[derive(Default)]
+struct Spill {
+    /// created on the first eviction, deleted along with the spill
+    file: Option<(File, PathBuf)>,
+    /// offset and length in `file` of each evicted (file id, piece)
+    slots: HashMap<(usize, u64), (u64, usize)>,
+    len: u64,
+}
+
+/// The parts of a file that have been written, keyed by the torrent piece they belong to
+struct SparseFile {
+    offset_in_torrent: u64,
+    len: u64,
+    pieces: RwLock<BTreeMap<u64, Box<[u8]>>>,
+    /// file offset of the most recent read, for when no stream of the file is being served
+    read_pos: AtomicU64,
+}
+
+impl StorageFactory for InMemStorageFactory {
+    type Storage = InMemStorage;
+
+    fn create(
+        &self,
+        shared: &librqbit::ManagedTorrentShared,
+        metadata: &librqbit::TorrentMetadata,
+    ) -> anyhow::Result<Self::Storage> {
+        let files = Arc::new(SparseFiles {
+            info_hash: shared.info_hash,
+            files: metadata
+                .file_infos
+                .iter()
+                .map(|file| SparseFile {
+                    offset_in_torrent: file.offset_in_torrent,
+                    len: file.len,
+                    pieces: RwLock::new(BTreeMap::new()),
+                    read_pos: AtomicU64::new(0),
+                })
+                .collect(),
+            piece_length: metadata.info.lengths().default_piece_length().into(),
+            keep_behind: self.limit.keep_behind,
+            budget: Arc::clone(&self.budget),
+            readers: Arc::clone(&self.readers),
+            spill: Mutex::default(),
+        });
+        self.budget.register(&files);
+        Ok(InMemStorage { files })
+    }
+
+    fn clone_box(&self) -> BoxStorageFactory {
+        self.clone().boxed()
+    }
+}
+
+impl SparseFile {
+    /// File-relative byte range of the part of `piece` inside this file
+    fn piece_range(&self, piece: u64, piece_length: u64) -> Range<u64> {
+        let start = (piece * piece_length).saturating_sub(self.offset_in_torrent);
+        let end = ((piece + 1) * piece_length - self.offset_in_torrent).min(self.len);
+        start..end
+    }
+    /// Pieces touched by the file-relative range `offset..offset+len`
+    fn pieces_for(&self, offset: u64, len: u64, piece_length: u64) -> Range<u64> {
+        let start = self.offset_in_torrent + offset;
+        start / piece_length..(start + len).div_ceil(piece_length)
+    }
+    /// Move pieces ending more than `keep_behind` before `read_pos` to `spill`, farthest first, until `want` bytes
+    /// are freed
+    fn evict_behind(
+        &self,
+        file_id: usize,
+        read_pos: u64,
+        keep_behind: u64,
+        piece_length: u64,
+        want: u64,
+        spill: &Mutex<Spill>,
+    ) -> u64 {
+        let cutoff = read_pos.saturating_sub(keep_behind);
+        // always locked after the pieces, reads of evicted pieces go through both
+        let mut pieces = self.pieces.write().expect("not poisoned");
+        let mut spill = spill.lock().expect("not poisoned");
+        let mut freed = 0;
+        while freed < want
+            && let Some(entry) = pieces.first_entry()
+            && self.piece_range(*entry.key(), piece_length).end <= cutoff
+        {
+            if let Err(e) = spill.put((file_id, *entry.key()), entry.get()) {
+                log::warn!("moving piece out of memory failed, keeping it: {e:#}");
+                break;
+            }
+            freed += entry.remove().len() as u64;
+        }
+        freed
+    }
+    fn clear(&self) -> u64 {
+        let mut pieces = self.pieces.write().expect("not poisoned");
+        std::mem::take(&mut *pieces)
+            .into_values()
+            .map(|v| v.len() as u64)
+            .sum()
+    }
+}
+
+impl SparseFiles {
+    fn evict_behind(&self, keep_behind: u64, want: u64) -> u64 {
+        let mut freed = 0;
+        for (file_id, file) in self.files.iter().enumerate() {
+            if freed >= want {
+                break;
+            }
+            // prefetching and hash checks read ahead, so prefer the positions of the readers being served
+            let read_pos = self
+                .readers
+                .min_position(self.info_hash, file_id)
+                .unwrap_or_else(|| file.read_pos.load(Ordering::Acquire));
+            freed += file.evict_behind(
+                file_id,
+                read_pos,
+                keep_behind,
+                self.piece_length,
+                want - freed,
+                &self.spill,
+            );
+        }
+        if freed > 0 {
+            self.budget.release(freed);
+        }
+        freed
+    }
+}
+
+impl Spill {
+    fn put(&mut self, key: (usize, u64), data: &[u8]) -> std::io::Result<()> {
+        let (file, _) = match &mut self.file {
+            Some(v) => v,
+            None => {
+                static NEXT: AtomicU64 = AtomicU64::new(0);
+                let path = std::env::temp_dir().join(format!(
+                    "rqstream-spill-{}-{}",
+                    std::process::id(),
+                    NEXT.fetch_add(1, Ordering::Relaxed)
+                ));
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                self.file.insert((file, path))
+            }
+        };
+        file.seek(SeekFrom::Start(self.len))?;
+        file.write_all(data)?;
+        self.slots.insert(key, (self.len, data.len()));
+        self.len += data.len() as u64;
+        Ok(())
+    }
+    /// Read `buf.len()` bytes from `start` bytes into an evicted piece
+    fn read(&mut self, key: (usize, u64), start: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+        let (offset, len) = *self
+            .slots
+            .get(&key)
+            .with_context(|| format!("piece {} was never written", key.1))?;
+        if start + buf.len() > len {
+            anyhow::bail!("out of bounds read of piece {}", key.1);
+        }
+        let (file, _) = self.file.as_mut().context("spill file is gone")?;
+        file.seek(SeekFrom::Start(offset + start as u64))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+    /// Take an evicted piece back, for writing to it again
+    fn take(&mut self, key: (usize, u64)) -> anyhow::Result<Option<Box<[u8]>>> {
+        let Some(&(_, len)) = self.slots.get(&key) else {
+            return Ok(None);
+        };
+        let mut data = vec![0; len].into_boxed_slice();
+        self.read(key, 0, &mut data)?;
+        self.slots.remove(&key);
+        Ok(Some(data))
+    }
+    fn forget_file(&mut self, file_id: usize) {
+        self.slots.retain(|(id, _), _| *id != file_id);
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Some((file, path)) = self.file.take() {
+            drop(file);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for SparseFiles {
+    fn drop(&mut self) {
+        let held = self.files.iter().map(SparseFile::clear).sum();
+        self.budget.release(held);
+    }
+}
+
+/// Keeps small files in memory and spills large ones, or any that would exceed the memory budget, to disk
+#[derive(Clone)]
+pub struct HybridStorageFactory {
+    max_in_memory_file: u64,
+    /// reserved per whole file, to decide which files stay in memory
+    budget: Arc<MemoryBudget>,
+    mem: InMemStorageFactory,
+}
+
+pub struct HybridStorage {
+    mem: Box<dyn TorrentStorage>,
+    disk: Box<dyn TorrentStorage>,
+    /// whether each file is kept in `mem`
+    in_memory: Vec<bool>,
+    budget: Arc<MemoryBudget>,
+    /// bytes of `budget` reserved for this torrent's in-memory files, released on drop
+    reserved: AtomicU64,
+}
+
+impl StorageFactory for HybridStorageFactory {
+    type Storage = HybridStorage;
+
+    fn create(
+        &self,
+        shared: &librqbit::ManagedTorrentShared,
+        metadata: &librqbit::TorrentMetadata,
+    ) -> anyhow::Result<Self::Storage> {
+        let mut reserved = 0;
+        let in_memory = metadata
+            .file_infos
+            .iter()
+            .map(|file| {
+                let fits = file.len <= self.max_in_memory_file && self.budget.try_reserve(file.len);
+                if fits {
+                    reserved += file.len;
+                }
+                fits
+            })
+            .collect();
+        Ok(HybridStorage {
+            mem: Box::new(self.mem.create(shared, metadata)?),
+            disk: Box::new(FilesystemStorageFactory::default().create(shared, metadata)?),
+            in_memory,
+            budget: Arc::clone(&self.budget),
+            reserved: AtomicU64::new(reserved),
+        })
+    }
+
+    fn clone_box(&self) -> BoxStorageFactory {
+        self.clone().boxed()
+    }
+}
+
+impl HybridStorage {
+    fn storage_for(&self, file_id: usize) -> &dyn TorrentStorage {
+        if self.in_memory.get(file_id).copied().unwrap_or(false) {
+            &*self.mem
+        } else {
+            &*self.disk
+        }
+    }
+}
+
+impl Drop for HybridStorage {
+    fn drop(&mut self) {
+        self.budget.release(*self.reserved.get_mut());
+    }
+}
+
+mod storage_impl {
+    use super::{HybridStorage, InMemStorage, SparseFiles};
+    use anyhow::{Context, anyhow};
+    use librqbit::storage::TorrentStorage;
+    use std::{
+        collections::btree_map::Entry,
+        sync::{Arc, Mutex, RwLock, atomic::Ordering},
+    };
+
+    impl TorrentStorage for InMemStorage {
+        fn init(
+            &mut self,
+            _shared: &librqbit::ManagedTorrentShared,
+            _metadata: &librqbit::TorrentMetadata,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn pread_exact(&self, file_id: usize, offset: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+            let piece_length = self.files.piece_length;
+            let file = self.files.files.get(file_id).context("file was none")?;
+            if offset + buf.len() as u64 > file.len {
+                return Err(anyhow!("out of bounds read of file"));
+            }
+            file.read_pos.store(offset, Ordering::Release);
+
+            let pieces = file.pieces.read().map_err(|_| anyhow!("bug"))?;
+            let mut pos = offset;
+            let mut buf = buf;
+            for piece in file.pieces_for(offset, buf.len() as u64, piece_length) {
+                let range = file.piece_range(piece, piece_length);
+                let start = (pos - range.start) as usize;
+                let n = buf.len().min((range.end - range.start) as usize - start);
+                let (now, rest) = buf.split_at_mut(n);
+                match pieces.get(&piece) {
+                    Some(data) => now.copy_from_slice(&data[start..start + n]),
+                    None => self.files.spill.lock().map_err(|_| anyhow!("bug"))?.read(
+                        (file_id, piece),
+                        start,
+                        now,
+                    )?,
+                }
+                buf = rest;
+                pos += n as u64;
+            }
+            Ok(())
+        }
+
+        fn pwrite_all(&self, file_id: usize, offset: u64, buf: &[u8]) -> anyhow::Result<()> {
+            let SparseFiles {
+                files,
+                piece_length,
+                keep_behind,
+                budget,
+                spill,
+                ..
+            } = &*self.files;
+            let file = files.get(file_id).context("file was none")?;
+            if offset + buf.len() as u64 > file.len {
+                return Err(anyhow!("out of bounds write of file"));
+            }
+
+            let mut pos = offset;
+            let mut buf = buf;
+            for piece in file.pieces_for(offset, buf.len() as u64, *piece_length) {
+                let range = file.piece_range(piece, *piece_length);
+                let piece_len = range.end - range.start;
+                let missing = !file
+                    .pieces
+                    .read()
+                    .map_err(|_| anyhow!("bug"))?
+                    .contains_key(&piece);
+                if missing {
+                    // reserve before locking, eviction may need this file's lock
+                    budget.reserve_evicting(piece_len, *keep_behind);
+                }
+                let mut pieces = file.pieces.write().map_err(|_| anyhow!("bug"))?;
+                let data = match pieces.entry(piece) {
+                    Entry::Occupied(entry) => {
+                        if missing {
+                            // written concurrently
+                            budget.release(piece_len);
+                        }
+                        entry.into_mut()
+                    }
+                    Entry::Vacant(entry) => {
+                        if !missing {
+                            // evicted in between, count it without evicting while holding the lock
+                            budget.reserve_unchecked(piece_len);
+                        }
+                        // an evicted piece keeps what was written to it before
+                        let data = spill
+                            .lock()
+                            .map_err(|_| anyhow!("bug"))?
+                            .take((file_id, piece))?;
+                        entry.insert(
+                            data.unwrap_or_else(|| vec![0; piece_len as usize].into_boxed_slice()),
+                        )
+                    }
+                };
+                let start = (pos - range.start) as usize;
+                let n = buf.len().min(data.len() - start);
+                data[start..start + n].copy_from_slice(&buf[..n]);
+                buf = &buf[n..];
+                pos += n as u64;
+            }
+            Ok(())
+        }
+
+        fn remove_file(&self, file_id: usize, _filename: &std::path::Path) -> anyhow::Result<()> {
+            // we can't remove outright but we can at least dealloc the pieces
+            let file = self.files.files.get(file_id).context("file was none")?;
+            self.files.budget.release(file.clear());
+            self.files
+                .spill
+                .lock()
+                .map_err(|_| anyhow!("bug"))?
+                .forget_file(file_id);
+            Ok(())
+        }
+
+        fn remove_directory_if_empty(&self, _path: &std::path::Path) -> anyhow::Result<()> {
+            // unsupported
+            Ok(())
+        }
+
+        fn ensure_file_length(&self, file_id: usize, _length: u64) -> anyhow::Result<()> {
+            // pieces are allocated as they are written
+            self.files.files.get(file_id).context("file was none")?;
+            Ok(())
+        }
+
+        fn take(&self) -> anyhow::Result<Box<dyn TorrentStorage>> {
+            let SparseFiles {
+                info_hash,
+                files,
+                piece_length,
+                keep_behind,
+                budget,
+                readers,
+                spill,
+            } = &*self.files;
+            let files = Arc::new(SparseFiles {
+                info_hash: *info_hash,
+                files: files
+                    .iter()
+                    .map(|file| super::SparseFile {
+                        offset_in_torrent: file.offset_in_torrent,
+                        len: file.len,
+                        // moving the pieces moves their share of the budget along with them
+                        pieces: RwLock::new(std::mem::take(
+                            &mut *file.pieces.write().expect("not poisoned"),
+                        )),
+                        read_pos: file.read_pos.load(Ordering::Acquire).into(),
+                    })
+                    .collect(),
+                piece_length: *piece_length,
+                keep_behind: *keep_behind,
+                budget: Arc::clone(budget),
+                readers: Arc::clone(readers),
+                spill: Mutex::new(std::mem::take(&mut *spill.lock().expect("not poisoned"))),
+            });
+            budget.register(&files);
+            Ok(Box::new(Self { files }) as Box<dyn TorrentStorage>)
+        }
+    }
+
+    impl TorrentStorage for HybridStorage {
+        fn init(
+            &mut self,
+            shared: &librqbit::ManagedTorrentShared,
+            metadata: &librqbit::TorrentMetadata,
+        ) -> anyhow::Result<()> {
+            self.mem.init(shared, metadata)?;
+            self.disk.init(shared, metadata)
+        }
+
+        fn pread_exact(&self, file_id: usize, offset: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+            self.storage_for(file_id).pread_exact(file_id, offset, buf)
+        }
+
+        fn pwrite_all(&self, file_id: usize, offset: u64, buf: &[u8]) -> anyhow::Result<()> {
+            self.storage_for(file_id).pwrite_all(file_id, offset, buf)
+        }
+
+        fn remove_file(&self, file_id: usize, filename: &std::path::Path) -> anyhow::Result<()> {
+            // the disk storage created a placeholder for in-memory files too
+            self.mem.remove_file(file_id, filename)?;
+            self.disk.remove_file(file_id, filename)
+        }
+
+        fn remove_directory_if_empty(&self, path: &std::path::Path) -> anyhow::Result<()> {
+            self.disk.remove_directory_if_empty(path)
+        }
+
+        fn ensure_file_length(&self, file_id: usize, length: u64) -> anyhow::Result<()> {
+            self.storage_for(file_id)
+                .ensure_file_length(file_id, length)
+        }
+
+        fn take(&self) -> anyhow::Result<Box<dyn TorrentStorage>> {
+            Ok(Box::new(Self {
+                mem: self.mem.take()?,
+                disk: self.disk.take()?,
+                in_memory: self.in_memory.clone(),
+                budget: Arc::clone(&self.budget),
+                // the reservation moves along with the data
+                reserved: self.reserved.swap(0, Ordering::AcqRel).into(),
+            }) as Box<dyn TorrentStorage>)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    };
+
+    use librqbit::{dht::Id20, storage::TorrentStorage};
+
+    use super::{InMemStorage, MemoryBudget, MemoryLimit, SparseFile, SparseFiles};
+
+    const PIECE: u64 = 16;
+
+    /// A storage for files of `lens` bytes, laid out one after the other
+    fn storage(lens: &[u64], limit: MemoryLimit) -> InMemStorage {
+        let budget = Arc::new(MemoryBudget::new(limit.ceiling));
+        let mut offset = 0;
+        let files = Arc::new(SparseFiles {
+            info_hash: Id20::default(),
+            files: lens
+                .iter()
+                .map(|&len| {
+                    offset += len;
+                    file(offset - len, len)
+                })
+                .collect(),
+            piece_length: PIECE,
+            keep_behind: limit.keep_behind,
+            budget: Arc::clone(&budget),
+            readers: Default::default(),
+            spill: Default::default(),
+        });
+        budget.register(&files);
+        InMemStorage { files }
+    }
+
+    fn file(offset_in_torrent: u64, len: u64) -> SparseFile {
+        SparseFile {
+            offset_in_torrent,
+            len,
+            pieces: Default::default(),
+            read_pos: AtomicU64::new(0),
+        }
+    }
+
+    fn in_memory(storage: &InMemStorage, file_id: usize) -> Vec<u64> {
+        storage.files.files[file_id]
+            .pieces
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    fn used(storage: &InMemStorage) -> u64 {
+        storage.files.budget.used.load(Ordering::Acquire)
+    }
+
+    #[test]
+    fn piece_math() {
+        // starts 10 bytes into the first piece, ends 14 bytes into the seventh
+        let f = file(10, 100);
+        assert_eq!(f.piece_range(0, PIECE), 0..6);
+        assert_eq!(f.piece_range(1, PIECE), 6..22);
+        assert_eq!(f.piece_range(6, PIECE), 86..100);
+        assert_eq!(f.pieces_for(0, 100, PIECE), 0..7);
+        assert_eq!(f.pieces_for(6, 16, PIECE), 1..2);
+        assert_eq!(f.pieces_for(5, 2, PIECE), 0..2);
+        assert_eq!(f.pieces_for(99, 1, PIECE), 6..7);
+    }
+
+    #[test]
+    fn budget() {
+        let budget = MemoryBudget::new(100);
+        assert!(budget.try_reserve(60));
+        assert!(!budget.try_reserve(50));
+        assert!(budget.try_reserve(40));
+        assert!(!budget.try_reserve(1));
+        budget.release(30);
+        assert!(budget.try_reserve(30));
+        // nothing to evict, so it goes over rather than failing
+        budget.reserve_evicting(10, 0);
+        assert_eq!(budget.used.load(Ordering::Acquire), 110);
+    }
+
+    #[test]
+    fn keep_behind_cutoff() {
+        let s = storage(&[64], MemoryLimit::default());
+        s.pwrite_all(0, 0, &[1; 64]).unwrap();
+        let f = &s.files.files[0];
+        // nothing ends 16 bytes before 10
+        assert_eq!(
+            f.evict_behind(0, 10, 16, PIECE, u64::MAX, &s.files.spill),
+            0
+        );
+        // only the first piece ends 16 bytes before 40
+        assert_eq!(
+            f.evict_behind(0, 40, 16, PIECE, u64::MAX, &s.files.spill),
+            16
+        );
+        assert_eq!(in_memory(&s, 0), [1, 2, 3]);
+        // stops once enough is freed
+        assert_eq!(f.evict_behind(0, 64, 0, PIECE, 1, &s.files.spill), 16);
+        assert_eq!(in_memory(&s, 0), [2, 3]);
+    }
+
+    #[test]
+    fn read_after_evict() {
+        let s = storage(
+            &[64],
+            MemoryLimit {
+                ceiling: 32,
+                keep_behind: 16,
+            },
+        );
+        let data: Vec<u8> = (0..64).collect();
+        s.pwrite_all(0, 0, &data[..48]).unwrap();
+        // over the ceiling rather than evicting what has not been read yet
+        assert_eq!(in_memory(&s, 0), [0, 1, 2]);
+        assert_eq!(used(&s), 48);
+
+        let mut buf = [0; 8];
+        s.pread_exact(0, 40, &mut buf).unwrap();
+        s.pwrite_all(0, 48, &data[48..]).unwrap();
+        assert_eq!(in_memory(&s, 0), [1, 2, 3]);
+        assert_eq!(used(&s), 48);
+
+        // evicted pieces are still there to seek back to
+        let mut buf = [0; 64];
+        s.pread_exact(0, 0, &mut buf).unwrap();
+        assert_eq!(buf[..], data[..]);
+
+        // and keep what was written to them when written to again
+        s.pwrite_all(0, 2, &[0xff; 4]).unwrap();
+        assert_eq!(in_memory(&s, 0), [0, 1, 2, 3]);
+        let mut buf = [0; 16];
+        s.pread_exact(0, 0, &mut buf).unwrap();
+        assert_eq!(buf[..2], data[..2]);
+        assert_eq!(buf[2..6], [0xff; 4]);
+        assert_eq!(buf[6..], data[6..16]);
+    }
+
+    #[test]
+    fn drop_releases_budget() {
+        let s = storage(
+            &[20, 44],
+            MemoryLimit {
+                ceiling: 32,
+                keep_behind: 0,
+            },
+        );
+        let budget = Arc::clone(&s.files.budget);
+        s.pwrite_all(0, 0, &[1; 20]).unwrap();
+        s.pwrite_all(1, 0, &[2; 44]).unwrap();
+        assert_eq!(budget.used.load(Ordering::Acquire), 64);
+        let mut buf = [0; 4];
+        s.pread_exact(1, 40, &mut buf).unwrap();
+        // the second file's first piece is short, as it starts 4 bytes into the piece
+        assert_eq!(s.files.evict_behind(0, u64::MAX), 12 + 16);
+        assert_eq!(budget.used.load(Ordering::Acquire), 36);
+        let spill = s
+            .files
+            .spill
+            .lock()
+            .unwrap()
+            .file
+            .as_ref()
+            .unwrap()
+            .1
+            .clone();
+        assert!(spill.exists());
+
+        drop(s);
+        assert_eq!(budget.used.load(Ordering::Acquire), 0);
+        assert!(!spill.exists());
+    }
+}
@@ -0,0 +1,178 @@
+// not every test uses every part of this
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use librqbit::{
+    AddTorrent, AddTorrentOptions, CreateTorrentOptions, ListenerMode, ListenerOptions, Session,
+    SessionOptions, create_torrent,
+};
+use rqstream::{Rqstream, RqstreamConfig, StorageConfig, StreamId, TorrentLease};
+
+const LEN: usize = 100 * 1024;
+
+/// Session settings that keep it off the DHT, so it only ever talks to the peers it is given
+pub fn offline() -> RqstreamConfig {
+    RqstreamConfig {
+        dht: false,
+        ..Default::default()
+    }
+}
+
+/// A session whose cache dir already holds the complete file of `torrent`, so nothing has to be downloaded
+pub struct Cached {
+    pub rq: Arc<Rqstream>,
+    pub torrent: Vec<u8>,
+    pub data: Vec<u8>,
+    pub cache_dir: PathBuf,
+}
+
+impl Cached {
+    pub async fn add(&self) -> TorrentLease {
+        self.rq
+            .add_managed(AddTorrent::from_bytes(self.torrent.clone()))
+            .await
+            .unwrap()
+    }
+}
+
+pub async fn cached(name: &str) -> Cached {
+    let cache_dir = std::env::temp_dir().join(format!("rqstream-{name}-{}", std::process::id()));
+    tokio::fs::create_dir_all(&cache_dir).await.unwrap();
+    let data: Vec<u8> = (0..LEN).map(|i| (i * 31 % 251) as u8).collect();
+    let path = cache_dir.join(format!("{name}.mkv"));
+    tokio::fs::write(&path, &data).await.unwrap();
+    let torrent = create_torrent(
+        &path,
+        CreateTorrentOptions {
+            piece_length: Some(16384),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let rq = Rqstream::create_with_config(
+        "127.0.0.1:0",
+        StorageConfig::Disk {
+            cache_dir: cache_dir.clone(),
+        },
+        Default::default(),
+        offline(),
+    )
+    .await
+    .unwrap();
+    Cached {
+        rq,
+        torrent: torrent.as_bytes().unwrap().to_vec(),
+        data,
+        cache_dir,
+    }
+}
+
+pub struct Served {
+    pub rq: Arc<Rqstream>,
+    pub lease: TorrentLease,
+    pub id: StreamId,
+    pub url: String,
+    pub data: Vec<u8>,
+    pub cache_dir: PathBuf,
+}
+
+/// Streams the file of [`cached`] under `name`
+pub async fn serve(name: &str) -> Served {
+    let cached = cached(name).await;
+    let lease = cached.add().await;
+    let rq = cached.rq;
+    let id = rq.stream_file(&lease, 0, name.to_string()).await.unwrap();
+    let url = rq.stream_url(id).await.unwrap();
+    Served {
+        rq,
+        lease,
+        id,
+        url,
+        data: cached.data,
+        cache_dir: cached.cache_dir,
+    }
+}
+
+/// A torrent seeded by a separate in-process session on loopback, without trackers or the DHT
+pub struct Swarm {
+    pub seeder: Arc<Session>,
+    /// where the seeder accepts peers
+    pub peer_addr: SocketAddr,
+    pub torrent: Vec<u8>,
+    pub data: Vec<u8>,
+    pub dir: PathBuf,
+}
+
+/// Seed a generated file of `len` bytes named after `name`
+pub async fn swarm(name: &str, len: usize) -> Swarm {
+    let dir = std::env::temp_dir().join(format!("rqstream-{name}-{}", std::process::id()));
+    let seed_dir = dir.join("seed");
+    tokio::fs::create_dir_all(&seed_dir).await.unwrap();
+    // not a multiple of the piece length, so the last piece is short
+    let data: Vec<u8> = (0..len).map(|i| (i * 7 % 253) as u8).collect();
+    let path = seed_dir.join(format!("{name}.mkv"));
+    tokio::fs::write(&path, &data).await.unwrap();
+    let torrent = create_torrent(
+        &path,
+        CreateTorrentOptions {
+            piece_length: Some(16384),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap()
+    .as_bytes()
+    .unwrap()
+    .to_vec();
+
+    let peer_addr = free_addr();
+    let seeder = Session::new_with_opts(
+        seed_dir,
+        SessionOptions {
+            disable_dht: true,
+            disable_dht_persistence: true,
+            persistence: None,
+            listen: Some(ListenerOptions {
+                mode: ListenerMode::TcpOnly,
+                listen_addr: peer_addr,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    seeder
+        .add_torrent(
+            AddTorrent::from_bytes(torrent.clone()),
+            Some(AddTorrentOptions {
+                overwrite: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap()
+        .into_handle()
+        .unwrap()
+        .wait_until_initialized()
+        .await
+        .unwrap();
+    Swarm {
+        seeder,
+        peer_addr,
+        torrent,
+        data,
+        dir,
+    }
+}
+
+/// A loopback address nothing listens on right now
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
@@ -0,0 +1,96 @@
+use librqbit::ManagedTorrent;
+use rqstream::Rqstream;
+
+mod common;
+use common::{Served, serve};
+
+async fn body(url: &str) -> Vec<u8> {
+    reqwest::get(url)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+fn is_managed(rq: &Rqstream, torrent: &ManagedTorrent) -> bool {
+    rq.session.get(torrent.id().into()).is_some()
+}
+
+#[tokio::test]
+async fn concurrent_streams() {
+    let Served {
+        rq,
+        lease,
+        id,
+        url,
+        data,
+        cache_dir,
+    } = serve("concurrent").await;
+
+    // the same route is requested twice at once
+    let (a, b) = tokio::join!(
+        rq.stream_file(&lease, 0, "episode.mkv".to_string()),
+        rq.stream_file(&lease, 0, "episode.mkv".to_string()),
+    );
+    let (a, b) = (a.unwrap(), b.unwrap());
+    let url_a = rq.stream_url(a).await.unwrap();
+    let url_b = rq.stream_url(b).await.unwrap();
+    let mut routes = [url_a.clone(), url_b.clone()];
+    routes.sort();
+    assert!(routes[0].ends_with("/stream/episode-2.mkv"), "{routes:?}");
+    assert!(routes[1].ends_with("/stream/episode.mkv"), "{routes:?}");
+
+    for url in [&url, &url_a, &url_b] {
+        assert_eq!(body(url).await, data);
+    }
+
+    // stopping one leaves the others alone
+    rq.stop_streaming(a).await.unwrap();
+    assert_eq!(
+        reqwest::get(&url_a).await.unwrap().status(),
+        reqwest::StatusCode::NOT_FOUND
+    );
+    assert_eq!(body(&url_b).await, data);
+    assert!(is_managed(&rq, &lease));
+    // a stopped stream can't be stopped again
+    assert!(rq.stop_streaming(a).await.is_err());
+
+    // the freed route is handed out again
+    let c = rq
+        .stream_file(&lease, 0, "episode.mkv".to_string())
+        .await
+        .unwrap();
+    assert_eq!(rq.stream_url(c).await.unwrap(), url_a);
+
+    rq.stop_streaming(id).await.unwrap();
+    rq.stop_streaming(b).await.unwrap();
+    let torrent = std::sync::Arc::clone(lease.torrent());
+    lease.release().await.unwrap();
+    assert!(is_managed(&rq, &torrent));
+    // the torrent goes away with the last stream
+    rq.stop_streaming(c).await.unwrap();
+    assert!(!is_managed(&rq, &torrent));
+
+    let _ = tokio::fs::remove_dir_all(cache_dir).await;
+}
+
+#[tokio::test]
+async fn invalid_routes() {
+    let Served {
+        rq,
+        lease,
+        cache_dir,
+        ..
+    } = serve("invalid").await;
+    for name in ["", "a/b", "sp ace.mkv", "ünicode.mkv"] {
+        assert!(
+            rq.stream_file(&lease, 0, name.to_string()).await.is_err(),
+            "{name:?}"
+        );
+    }
+    let _ = tokio::fs::remove_dir_all(cache_dir).await;
+}
@@ -0,0 +1,352 @@
+//! Streams a torrent from a seeder in the same process, so none of this needs network access
+use std::time::Duration;
+
+use reqwest::{StatusCode, header};
+use rqstream::{MemoryLimit, ReadAheadConfig, Rqstream, RqstreamConfig, SeedPolicy, StorageConfig};
+
+mod common;
+use common::{Swarm, offline, swarm};
+
+/// Everything here is on loopback, so this only runs out if something is stuck
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn get_range(url: &str, range: Option<&str>) -> (StatusCode, Vec<u8>) {
+    let mut req = reqwest::Client::new().get(url);
+    if let Some(range) = range {
+        req = req.header(header::RANGE, range);
+    }
+    let resp = tokio::time::timeout(TIMEOUT, req.send())
+        .await
+        .expect("response in time")
+        .unwrap();
+    let status = resp.status();
+    let body = tokio::time::timeout(TIMEOUT, resp.bytes())
+        .await
+        .expect("body in time")
+        .unwrap();
+    (status, body.to_vec())
+}
+
+#[tokio::test]
+async fn stream_from_swarm() {
+    let Swarm {
+        seeder: _seeder,
+        peer_addr,
+        torrent,
+        data,
+        dir,
+    } = swarm("e2e", 3 * 1024 * 1024 + 1234).await;
+    let len = data.len();
+
+    let rq = Rqstream::create_with_config(
+        "127.0.0.1:0",
+        StorageConfig::InMemory(MemoryLimit::default()),
+        ReadAheadConfig::default(),
+        offline(),
+    )
+    .await
+    .unwrap();
+    let lease = rq
+        .add_managed_with_peers(librqbit::AddTorrent::from_bytes(torrent), vec![peer_addr])
+        .await
+        .unwrap();
+    let torrent = std::sync::Arc::clone(lease.torrent());
+    let id = rq
+        .stream_file(&lease, 0, "e2e.mkv".to_string())
+        .await
+        .unwrap();
+    drop(lease);
+    let url = rq.stream_url(id).await.unwrap();
+
+    // seek around the way a player does, starting far from the beginning
+    for (range, expected) in [
+        ("bytes=2000000-2100000", 2_000_000..2_100_001),
+        ("bytes=-1000", len - 1000..len),
+        ("bytes=0-99", 0..100),
+        ("bytes=1048570-1048580", 1_048_570..1_048_581),
+        ("bytes=3000000-", 3_000_000..len),
+    ] {
+        let (status, body) = get_range(&url, Some(range)).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT, "{range}");
+        assert!(body == data[expected], "{range} differs");
+    }
+
+    let (status, body) = get_range(&url, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body == data, "full body differs");
+
+    let stats = rq.stream_stats(id).await.unwrap();
+    assert_eq!(stats.downloaded_bytes, len as u64);
+
+    rq.stop_streaming(id).await.unwrap();
+    let (status, _) = get_range(&url, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(rq.stream_stats(id).await.is_none());
+    // the lease is long gone, so the torrent went with its last stream
+    assert!(rq.session.get(torrent.id().into()).is_none());
+
+    let _ = tokio::fs::remove_dir_all(dir).await;
+}
+
+#[tokio::test]
+async fn download_from_swarm() {
+    let Swarm {
+        seeder: _seeder,
+        peer_addr,
+        torrent,
+        data,
+        dir,
+    } = swarm("e2e-download", 2 * 1024 * 1024 + 321).await;
+    let library = dir.join("library");
+
+    let rq = Rqstream::create_with_config(
+        "127.0.0.1:0",
+        StorageConfig::InMemory(MemoryLimit::default()),
+        ReadAheadConfig::default(),
+        offline(),
+    )
+    .await
+    .unwrap();
+
+    // refused without downloading anything
+    let too_small = rq
+        .download_with_peers(
+            librqbit::AddTorrent::from_bytes(torrent.clone()),
+            vec![peer_addr],
+            0,
+            library.clone(),
+            Some(1024),
+        )
+        .await;
+    assert!(too_small.is_err());
+    assert_eq!(rq.session.with_torrents(|v| v.count()), 0);
+
+    let download = tokio::spawn({
+        let rq = std::sync::Arc::clone(&rq);
+        let torrent = torrent.clone();
+        let library = library.clone();
+        async move {
+            rq.download_with_peers(
+                librqbit::AddTorrent::from_bytes(torrent),
+                vec![peer_addr],
+                0,
+                library,
+                None,
+            )
+            .await
+        }
+    });
+    // streaming while it downloads shares the download
+    tokio::time::timeout(TIMEOUT, async {
+        while rq.session.with_torrents(|v| v.count()) == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("download to start");
+    let lease = rq
+        .add_managed(librqbit::AddTorrent::from_bytes(torrent.clone()))
+        .await
+        .unwrap();
+    let id = rq
+        .stream_file(&lease, 0, "e2e-download.mkv".to_string())
+        .await
+        .unwrap();
+    drop(lease);
+    let url = rq.stream_url(id).await.unwrap();
+    let (status, body) = get_range(&url, Some("bytes=-1000")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert!(body == data[data.len() - 1000..]);
+
+    let path = tokio::time::timeout(TIMEOUT, download)
+        .await
+        .expect("download in time")
+        .unwrap()
+        .unwrap();
+    assert!(path.starts_with(&library));
+    // the stream still holds the torrent, and the download is kept once it lets go
+    rq.stop_streaming(id).await.unwrap();
+    assert_eq!(rq.session.with_torrents(|v| v.count()), 0);
+    assert!(tokio::fs::read(&path).await.unwrap() == data);
+
+    let _ = tokio::fs::remove_dir_all(dir).await;
+}
+
+#[tokio::test]
+async fn pause_unless_shared() {
+    let Swarm {
+        seeder: _seeder,
+        peer_addr,
+        torrent,
+        data,
+        dir,
+    } = swarm("e2e-pause", 1024 * 1024 + 77).await;
+
+    let rq = Rqstream::create_with_config(
+        "127.0.0.1:0",
+        StorageConfig::InMemory(MemoryLimit::default()),
+        ReadAheadConfig::default(),
+        offline(),
+    )
+    .await
+    .unwrap();
+    let lease = rq
+        .add_managed_with_peers(
+            librqbit::AddTorrent::from_bytes(torrent.clone()),
+            vec![peer_addr],
+        )
+        .await
+        .unwrap();
+    let first = rq
+        .stream_file(&lease, 0, "e2e-pause.mkv".to_string())
+        .await
+        .unwrap();
+    let second = rq
+        .stream_file(&lease, 0, "e2e-pause.mkv".to_string())
+        .await
+        .unwrap();
+    let managed = std::sync::Arc::clone(lease.torrent());
+    lease.release().await.unwrap();
+
+    // the other stream still needs it
+    assert!(!rq.pause(&managed).await.unwrap());
+    assert!(!managed.is_paused());
+    rq.stop_streaming(second).await.unwrap();
+    assert!(rq.pause(&managed).await.unwrap());
+    assert!(managed.is_paused());
+
+    // streaming it again picks the paused torrent back up rather than adding it anew
+    let lease = rq
+        .add_managed(librqbit::AddTorrent::from_bytes(torrent))
+        .await
+        .unwrap();
+    assert_eq!(lease.torrent().id(), managed.id());
+    let third = rq
+        .stream_file(&lease, 0, "e2e-pause.mkv".to_string())
+        .await
+        .unwrap();
+    lease.release().await.unwrap();
+    assert!(!managed.is_paused());
+    let (status, body) = get_range(&rq.stream_url(third).await.unwrap(), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body == data);
+
+    rq.stop_streaming(first).await.unwrap();
+    rq.stop_streaming(third).await.unwrap();
+    assert!(rq.session.get(managed.id().into()).is_none());
+
+    let _ = tokio::fs::remove_dir_all(dir).await;
+}
+
+#[tokio::test]
+async fn seed_after_streaming() {
+    let first = swarm("e2e-seed-a", 512 * 1024 + 5).await;
+    let second = swarm("e2e-seed-b", 512 * 1024 + 9).await;
+
+    // seeding needs the data on disk
+    let rq = Rqstream::create_with_config(
+        "127.0.0.1:0",
+        StorageConfig::Disk {
+            cache_dir: first.dir.join("cache"),
+        },
+        ReadAheadConfig::default(),
+        RqstreamConfig {
+            seed: SeedPolicy {
+                ratio: None,
+                time_secs: Some(2),
+            },
+            max_torrents: Some(1),
+            ..offline()
+        },
+    )
+    .await
+    .unwrap();
+    let stream = |swarm: &Swarm, name: &'static str| {
+        let rq = std::sync::Arc::clone(&rq);
+        let torrent = librqbit::AddTorrent::from_bytes(swarm.torrent.clone());
+        let peers = vec![swarm.peer_addr];
+        async move {
+            let lease = rq.add_managed_with_peers(torrent, peers).await?;
+            let id = rq.stream_file(&lease, 0, name.to_string()).await?;
+            lease.release().await?;
+            anyhow::Ok(id)
+        }
+    };
+
+    let id = stream(&first, "e2e-seed-a.mkv").await.unwrap();
+    let (status, body) = get_range(&rq.stream_url(id).await.unwrap(), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body == first.data);
+    rq.stop_streaming(id).await.unwrap();
+    // still seeding, and already taking up the only slot
+    assert_eq!(rq.session.with_torrents(|v| v.count()), 1);
+
+    // a streaming torrent cannot be removed to make room for another
+    let id = stream(&second, "e2e-seed-b.mkv").await.unwrap();
+    assert!(stream(&first, "e2e-seed-a.mkv").await.is_err());
+    assert_eq!(rq.session.with_torrents(|v| v.count()), 1);
+    rq.stop_streaming(id).await.unwrap();
+
+    // until it is done seeding
+    tokio::time::timeout(TIMEOUT, async {
+        while rq.session.with_torrents(|v| v.count()) > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("seeding to end");
+
+    let _ = tokio::fs::remove_dir_all(first.dir).await;
+    let _ = tokio::fs::remove_dir_all(second.dir).await;
+}
+
+#[tokio::test]
+async fn hybrid_spills_large_files() {
+    let Swarm {
+        seeder: _seeder,
+        peer_addr,
+        torrent,
+        data,
+        dir,
+    } = swarm("e2e-hybrid", 1024 * 1024 + 55).await;
+    let cache_dir = dir.join("cache");
+
+    let rq = Rqstream::create_with_config(
+        "127.0.0.1:0",
+        StorageConfig::Hybrid {
+            cache_dir: cache_dir.clone(),
+            max_in_memory_file: 256 * 1024,
+            memory: MemoryLimit::default(),
+        },
+        ReadAheadConfig::default(),
+        offline(),
+    )
+    .await
+    .unwrap();
+    let lease = rq
+        .add_managed_with_peers(librqbit::AddTorrent::from_bytes(torrent), vec![peer_addr])
+        .await
+        .unwrap();
+    let id = rq
+        .stream_file(&lease, 0, "e2e-hybrid.mkv".to_string())
+        .await
+        .unwrap();
+    drop(lease);
+    let (status, body) = get_range(&rq.stream_url(id).await.unwrap(), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body == data);
+    // too large to be kept in memory
+    let on_disk = tokio::fs::read(cache_dir.join("e2e-hybrid.mkv"))
+        .await
+        .unwrap();
+    assert!(on_disk == data, "file on disk differs");
+
+    rq.stop_streaming(id).await.unwrap();
+    let mut entries = tokio::fs::read_dir(&cache_dir).await.unwrap();
+    assert!(
+        entries.next_entry().await.unwrap().is_none(),
+        "cache dir not emptied"
+    );
+
+    let _ = tokio::fs::remove_dir_all(dir).await;
+}
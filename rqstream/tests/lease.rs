@@ -0,0 +1,84 @@
+use std::{sync::Arc, time::Duration};
+
+use librqbit::ManagedTorrent;
+use rqstream::Rqstream;
+
+mod common;
+use common::cached;
+
+fn is_managed(rq: &Rqstream, torrent: &ManagedTorrent) -> bool {
+    rq.session.get(torrent.id().into()).is_some()
+}
+
+/// Dropped leases are released in the background
+async fn wait_removed(rq: &Rqstream, torrent: &ManagedTorrent) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while is_managed(rq, torrent) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("torrent to be removed");
+}
+
+#[tokio::test]
+async fn unstreamed_lease() {
+    let cached = cached("lease-unstreamed").await;
+    let rq = &cached.rq;
+
+    let lease = cached.add().await;
+    let torrent = Arc::clone(lease.torrent());
+    assert!(is_managed(rq, &torrent));
+    drop(lease);
+    wait_removed(rq, &torrent).await;
+
+    // it can be added again afterwards
+    let lease = cached.add().await;
+    let torrent = Arc::clone(lease.torrent());
+    assert!(is_managed(rq, &torrent));
+    lease.release().await.unwrap();
+    assert!(!is_managed(rq, &torrent));
+
+    let _ = tokio::fs::remove_dir_all(&cached.cache_dir).await;
+}
+
+#[tokio::test]
+async fn leases_and_streams() {
+    let cached = cached("lease-streams").await;
+    let rq = &cached.rq;
+
+    // two leases of one torrent
+    let a = cached.add().await;
+    let b = cached.add().await;
+    let torrent = Arc::clone(a.torrent());
+    assert_eq!(a.info_hash(), b.info_hash());
+    a.release().await.unwrap();
+    assert!(is_managed(rq, &torrent));
+
+    // a stream outlives its lease
+    let stream = rq.stream_file(&b, 0, "ep.mkv".to_string()).await.unwrap();
+    b.release().await.unwrap();
+    assert!(is_managed(rq, &torrent));
+    let url = rq.stream_url(stream).await.unwrap();
+    let body = reqwest::get(&url).await.unwrap().bytes().await.unwrap();
+    assert_eq!(body, cached.data);
+    rq.stop_streaming(stream).await.unwrap();
+    assert!(!is_managed(rq, &torrent));
+
+    // a lease outlives its stream
+    let lease = cached.add().await;
+    let torrent = Arc::clone(lease.torrent());
+    let stream = rq
+        .stream_file(&lease, 0, "ep.mkv".to_string())
+        .await
+        .unwrap();
+    rq.stop_streaming(stream).await.unwrap();
+    assert!(is_managed(rq, &torrent));
+    // stopping twice doesn't release anything else
+    assert!(rq.stop_streaming(stream).await.is_err());
+    assert!(is_managed(rq, &torrent));
+    drop(lease);
+    wait_removed(rq, &torrent).await;
+
+    let _ = tokio::fs::remove_dir_all(&cached.cache_dir).await;
+}
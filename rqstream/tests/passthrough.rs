@@ -0,0 +1,198 @@
+//! Local files and upstream URLs served through the same routes as torrents
+use axum::{
+    Router,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use reqwest::{StatusCode, header};
+use rqstream::{ReadAheadConfig, Rqstream, StorageConfig};
+
+fn data() -> Vec<u8> {
+    (0..100 * 1024).map(|i| (i * 13 % 241) as u8).collect()
+}
+
+async fn rqstream(name: &str) -> std::sync::Arc<Rqstream> {
+    let cache_dir = std::env::temp_dir().join(format!("rqstream-{name}-{}", std::process::id()));
+    Rqstream::create(
+        "127.0.0.1:0",
+        StorageConfig::Disk { cache_dir },
+        ReadAheadConfig::default(),
+    )
+    .await
+    .unwrap()
+}
+
+async fn get(url: &str, range: Option<&str>) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let mut req = reqwest::Client::new().get(url);
+    if let Some(range) = range {
+        req = req.header(header::RANGE, range);
+    }
+    let resp = req.send().await.unwrap();
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    (status, headers, resp.bytes().await.unwrap().to_vec())
+}
+
+#[tokio::test]
+async fn local_file() {
+    let rq = rqstream("local").await;
+    let data = data();
+    let path = std::env::temp_dir().join(format!("rqstream-local-{}.mp4", std::process::id()));
+    tokio::fs::write(&path, &data).await.unwrap();
+
+    let id = rq.stream_local(&path, "local.mp4".into()).await.unwrap();
+    let url = rq.stream_url(id).await.unwrap();
+
+    let (status, headers, body) = get(&url, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "video/mp4");
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(body, data);
+
+    let (status, headers, body) = get(&url, Some("bytes=1000-1999")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        headers[header::CONTENT_RANGE],
+        format!("bytes 1000-1999/{}", data.len())
+    );
+    assert_eq!(body, data[1000..2000]);
+
+    // nothing to download, so nothing to report
+    assert!(rq.stream_stats(id).await.is_none());
+    rq.stop_streaming(id).await.unwrap();
+    assert_eq!(get(&url, None).await.0, StatusCode::NOT_FOUND);
+    // stopping never touches the file itself
+    assert!(path.exists());
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    assert!(rq.stream_local(&path, "gone.mp4".into()).await.is_err());
+}
+
+const REFERER: &str = "https://hoster.example/embed";
+
+/// Only answers requests with the right `Referer`, like the hosters this is for
+async fn upstream(headers: HeaderMap) -> Response {
+    if headers.get(header::REFERER).is_none_or(|v| v != REFERER) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let data = data();
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes="))
+        .and_then(|v| v.split_once('-'))
+        .map(|(first, last)| (first.parse().unwrap(), last.parse::<usize>().unwrap()));
+    match range {
+        Some((first, last)) => (
+            StatusCode::PARTIAL_CONTENT,
+            [
+                (header::CONTENT_TYPE, "video/webm".to_string()),
+                (header::ETAG, "\"upstream\"".to_string()),
+                (
+                    header::CONTENT_RANGE,
+                    format!("bytes {first}-{last}/{}", data.len()),
+                ),
+            ],
+            data[first..=last].to_vec(),
+        )
+            .into_response(),
+        None => (
+            [
+                (header::CONTENT_TYPE, "video/webm"),
+                (header::ETAG, "\"upstream\""),
+            ],
+            data,
+        )
+            .into_response(),
+    }
+}
+
+/// Ignores ranges and does not say what it serves
+async fn upstream_plain() -> Response {
+    ([(header::CONTENT_TYPE, "application/octet-stream")], data()).into_response()
+}
+
+async fn serve_upstream() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = Router::new()
+        .route("/video", get(upstream))
+        .route("/plain", get(upstream_plain));
+    tokio::spawn(async { axum::serve(listener, router).await.unwrap() });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn http_passthrough() {
+    let rq = rqstream("http").await;
+    let upstream = serve_upstream().await;
+    let data = data();
+
+    assert!(
+        rq.stream_http(
+            &format!("{upstream}/video"),
+            [] as [(&str, &str); 0],
+            "denied".into()
+        )
+        .await
+        .is_err()
+    );
+
+    let id = rq
+        .stream_http(
+            &format!("{upstream}/video"),
+            [("Referer", REFERER), ("User-Agent", "rqstream-test")],
+            "video".into(),
+        )
+        .await
+        .unwrap();
+    let url = rq.stream_url(id).await.unwrap();
+
+    let (status, headers, body) = get(&url, None).await;
+    assert_eq!(status, StatusCode::OK);
+    // from upstream rather than the route name
+    assert_eq!(headers[header::CONTENT_TYPE], "video/webm");
+    assert_eq!(headers[header::ETAG], "\"upstream\"");
+    assert_eq!(body, data);
+
+    let (status, _, body) = get(&url, Some("bytes=-100")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, data[data.len() - 100..]);
+
+    let (status, headers, body) = get(&url, Some("bytes=0-9,50-59")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert!(
+        headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("multipart/byteranges")
+    );
+    assert_eq!(
+        body.len() as u64,
+        headers[header::CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    );
+
+    let plain = rq
+        .stream_http(
+            &format!("{upstream}/plain"),
+            [] as [(&str, &str); 0],
+            "plain.mkv".into(),
+        )
+        .await
+        .unwrap();
+    let url = rq.stream_url(plain).await.unwrap();
+    // ranges cannot be served, so the whole body is sent instead
+    let (status, headers, body) = get(&url, Some("bytes=1000-1999")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::ACCEPT_RANGES], "none");
+    assert_eq!(headers[header::CONTENT_TYPE], "video/x-matroska");
+    assert_eq!(body, data);
+
+    rq.stop_streaming(id).await.unwrap();
+    rq.stop_streaming(plain).await.unwrap();
+}
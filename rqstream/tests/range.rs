@@ -0,0 +1,177 @@
+use reqwest::{StatusCode, header};
+
+mod common;
+use common::{Served, serve};
+
+async fn get(url: &str, headers: &[(header::HeaderName, &str)]) -> reqwest::Response {
+    let mut req = reqwest::Client::new().get(url);
+    for (k, v) in headers {
+        req = req.header(k, *v);
+    }
+    req.send().await.unwrap()
+}
+
+fn header<'a>(resp: &'a reqwest::Response, name: header::HeaderName) -> Option<&'a str> {
+    resp.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn ranges() {
+    let Served {
+        url,
+        data,
+        cache_dir,
+        ..
+    } = serve("range").await;
+    let len = data.len();
+
+    let resp = get(&url, &[]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, header::ACCEPT_RANGES), Some("bytes"));
+    assert_eq!(
+        header(&resp, header::CONTENT_TYPE),
+        Some("video/x-matroska")
+    );
+    assert!(header(&resp, header::ETAG).is_some());
+    assert_eq!(resp.bytes().await.unwrap(), data);
+
+    for (range, expected) in [
+        ("bytes=100-199", 100..200),
+        ("bytes=1000-", 1000..len),
+        ("bytes=-500", len - 500..len),
+        ("bytes=50000-999999", 50000..len),
+    ] {
+        let resp = get(&url, &[(header::RANGE, range)]).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT, "{range}");
+        assert_eq!(
+            header(&resp, header::CONTENT_RANGE),
+            Some(&*format!(
+                "bytes {}-{}/{len}",
+                expected.start,
+                expected.end - 1
+            )),
+            "{range}"
+        );
+        assert_eq!(
+            header(&resp, header::CONTENT_LENGTH),
+            Some(&*expected.len().to_string())
+        );
+        assert_eq!(resp.bytes().await.unwrap(), data[expected], "{range}");
+    }
+
+    for range in [
+        format!("bytes={len}-"),
+        format!("bytes={}-{}", len + 1, len + 5),
+    ] {
+        let resp = get(&url, &[(header::RANGE, &range)]).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{range}");
+        assert_eq!(
+            header(&resp, header::CONTENT_RANGE),
+            Some(&*format!("bytes */{len}"))
+        );
+    }
+
+    // malformed ranges are ignored
+    let resp = get(&url, &[(header::RANGE, "bytes=20-10")]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.bytes().await.unwrap().len(), len);
+
+    let _ = tokio::fs::remove_dir_all(cache_dir).await;
+}
+
+#[tokio::test]
+async fn multiple_ranges() {
+    let Served {
+        url,
+        data,
+        cache_dir,
+        ..
+    } = serve("multirange").await;
+    let len = data.len();
+
+    let resp = get(&url, &[(header::RANGE, "bytes=200-209,0-9")]).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = header(&resp, header::CONTENT_TYPE).unwrap().to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let content_length: usize = header(&resp, header::CONTENT_LENGTH)
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let mut expected = Vec::new();
+    for range in [0..10, 200..210] {
+        expected.extend_from_slice(
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: video/x-matroska\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n",
+                range.start,
+                range.end - 1
+            )
+            .as_bytes(),
+        );
+        expected.extend_from_slice(&data[range]);
+    }
+    expected.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    let body = resp.bytes().await.unwrap();
+    assert_eq!(body.len(), content_length);
+    assert_eq!(body, expected);
+
+    let _ = tokio::fs::remove_dir_all(cache_dir).await;
+}
+
+#[tokio::test]
+async fn head_and_if_range() {
+    let Served {
+        url,
+        data,
+        cache_dir,
+        ..
+    } = serve("head").await;
+    let len = data.len();
+
+    let resp = reqwest::Client::new()
+        .head(&url)
+        .header(header::RANGE, "bytes=10-19")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(header(&resp, header::CONTENT_LENGTH), Some("10"));
+    assert_eq!(
+        header(&resp, header::CONTENT_RANGE),
+        Some(&*format!("bytes 10-19/{len}"))
+    );
+    let etag = header(&resp, header::ETAG).unwrap().to_string();
+    assert!(resp.bytes().await.unwrap().is_empty());
+
+    let resp = reqwest::Client::new().head(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        header(&resp, header::CONTENT_LENGTH),
+        Some(&*len.to_string())
+    );
+
+    // a matching validator honours the range
+    let resp = get(
+        &url,
+        &[(header::RANGE, "bytes=10-19"), (header::IF_RANGE, &etag)],
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.bytes().await.unwrap(), data[10..20]);
+
+    // anything else gets the whole file
+    for if_range in ["\"something-else\"", "Wed, 21 Oct 2015 07:28:00 GMT"] {
+        let resp = get(
+            &url,
+            &[(header::RANGE, "bytes=10-19"), (header::IF_RANGE, if_range)],
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK, "{if_range}");
+        assert_eq!(resp.bytes().await.unwrap(), data);
+    }
+
+    let _ = tokio::fs::remove_dir_all(cache_dir).await;
+}
@@ -0,0 +1,67 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+
+mod common;
+use common::{Served, serve};
+
+#[tokio::test]
+async fn status() {
+    let Served {
+        rq,
+        id,
+        url,
+        data,
+        cache_dir,
+        ..
+    } = serve("status").await;
+    let len = data.len() as u64;
+
+    let stats = rq.stream_stats(id).await.unwrap();
+    assert_eq!(&*stats.path, "status");
+    assert_eq!(stats.total_bytes, len);
+    // everything was on disk already
+    assert_eq!(stats.downloaded_bytes, len);
+    assert_eq!(stats.eta, Some(std::time::Duration::ZERO));
+    assert_eq!(stats.buffered, None);
+
+    let base = format!("http://{}", rq.local_addr());
+    let all: Value = reqwest::get(format!("{base}/status"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let all = all.as_array().unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["path"], "status");
+    assert_eq!(all[0]["total_bytes"], len);
+
+    let stream_id = &all[0]["id"];
+    let one: Value = reqwest::get(format!("{base}/status/{stream_id}"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(one["downloaded_bytes"], len);
+    assert_eq!(one["eta"], 0.0);
+
+    let resp = reqwest::get(format!("{base}/status/9999")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // while a response is being read, its position is reported
+    let resp = reqwest::Client::new()
+        .get(&url)
+        .header(reqwest::header::RANGE, "bytes=1000-")
+        .send()
+        .await
+        .unwrap();
+    let buffered = rq.stream_stats(id).await.unwrap().buffered.unwrap();
+    assert!(buffered.start >= 1000, "{buffered:?}");
+    drop(resp);
+
+    rq.stop_streaming(id).await.unwrap();
+    assert!(rq.stream_stats(id).await.is_none());
+
+    let _ = tokio::fs::remove_dir_all(cache_dir).await;
+}
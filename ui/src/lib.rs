@@ -2,6 +2,7 @@ use app::{
     AddAnime, Config, Message, ModifyShow, Monsoon, NameKind,
     media::PlayRequest,
     show::{Show, ShowId},
+    source::{Translation, TranslationPreference},
 };
 use helpers::{info_text, large_bold, sizes::WithSizeExt, subdivision::Subdivision};
 use iced::{
@@ -71,13 +72,72 @@ impl MonsoonExt for Monsoon {
         } else if let Some(&id) = self.more_info_windows.get(&window)
             && let Some(s) = self.db.shows.get(id)
         {
-            let content = view_show_inlay(self, s, id);
+            let content = widget::column![view_show_inlay(self, s, id), view_show_prefs(self, id)]
+                .spacing(UI_SIZES.size10.get());
             widget::scrollable(content).into()
         } else {
             unimplemented!()
         }
     }
 }
+fn view_show_prefs(m: &'_ Monsoon, id: ShowId) -> Element<'_, Message> {
+    let sz = UI_SIZES.info_font_size.get();
+    let own = m.db.show_prefs.get(id).and_then(|v| v.translation.as_ref());
+    // edits start out from the global preference, which the show's own one then replaces
+    let current = own.unwrap_or(&m.config.translation).0.clone();
+    let set = move |v: Vec<Translation>| {
+        Message::ModifyShow(
+            id,
+            ModifyShow::SetTranslation(Some(TranslationPreference(v))),
+        )
+    };
+    let entries = current.iter().enumerate().map(|(idx, t)| {
+        let earlier = (idx > 0).then(|| {
+            let mut v = current.clone();
+            v.swap(idx - 1, idx);
+            set(v)
+        });
+        // an empty preference would accept nothing at all
+        let removed = (current.len() > 1).then(|| {
+            let mut v = current.clone();
+            v.remove(idx);
+            set(v)
+        });
+        row![
+            info_text(t.as_str(), sz),
+            widget::button(info_text("<", sz)).on_press_maybe(earlier),
+            widget::button(info_text("x", sz)).on_press_maybe(removed),
+        ]
+        .spacing(UI_SIZES.size10.get())
+        .align_y(A::Center)
+        .into()
+    });
+    let missing: Vec<_> = Translation::ALL
+        .into_iter()
+        .filter(|t| !current.contains(t))
+        .collect();
+    row![info_text("preferred translations", sz)]
+        .extend(entries)
+        .push(
+            widget::pick_list(missing, None::<Translation>, move |t| {
+                let mut v = current.clone();
+                v.push(t);
+                set(v)
+            })
+            .placeholder("add")
+            .text_size(sz),
+        )
+        .push(
+            widget::button(info_text("reset", sz)).on_press_maybe(
+                own.map(|_| Message::ModifyShow(id, ModifyShow::SetTranslation(None))),
+            ),
+        )
+        .spacing(UI_SIZES.size10.get())
+        .padding(UI_SIZES.pad10.get())
+        .align_y(A::Center)
+        .into()
+}
+
 fn view_show_inlay<'a>(m: &Monsoon, s: &'a Show, id: ShowId) -> Container<'a, Message> {
     let name: &str = s.get_preferred_name(&m.config);
 